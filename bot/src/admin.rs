@@ -0,0 +1,771 @@
+use std::fmt;
+
+use anyhow::{anyhow, bail};
+use clap::Subcommand;
+use ethers::{
+    providers::Middleware,
+    types::{Address, BlockNumber, TxHash, U256},
+    utils::{format_units, to_checksum},
+};
+use serde::Serialize;
+
+use crate::{
+    config::{
+        ChainArgs, ChainConfig, ConfigArgs, DuolingoArgs, DuolingoConfig, StorageArgs,
+        StorageConfig,
+    },
+    connect,
+    duolingo::add_address_to_profile,
+    duolingo_client, find_account, indexer, jwt, reconcile,
+    tx::{explain_revert, TxOutcome},
+    Connections, XpProgress,
+};
+
+// Operator commands that do what the bot's handlers do, without Telegram or
+// the ownership checks.
+#[derive(Subcommand)]
+pub enum AdminCommand {
+    /// Register a linked Duolingo user with the contract, or update their
+    /// address if it has changed
+    Register {
+        username: String,
+
+        #[clap(flatten)]
+        args: AdminArgs,
+    },
+    /// Remove a Duolingo user from the contract
+    Unregister {
+        username: String,
+
+        #[clap(flatten)]
+        args: AdminArgs,
+    },
+    /// Change the address a registered user is paid out to
+    UpdateAddress {
+        username: String,
+
+        /// Defaults to the address the user verified with /linkwallet, or
+        /// else the one in their Duolingo bio.
+        address: Option<Address>,
+
+        #[clap(flatten)]
+        args: AdminArgs,
+    },
+    /// Report a user's XP, minting POD for what they gained since the last
+    /// report
+    ReportXp {
+        username: String,
+
+        #[clap(flatten)]
+        args: AdminArgs,
+    },
+    /// Show what Duolingo, the contract and the bot know about a user
+    ShowUser {
+        username: String,
+
+        #[clap(flatten)]
+        args: AdminArgs,
+    },
+    /// Index the contract's events up to the latest confirmed block
+    Index {
+        /// Where to start if nothing has been indexed for this contract yet,
+        /// e.g. the block it was deployed in.
+        #[clap(long, default_value_t = 0)]
+        from_block: u64,
+
+        #[clap(flatten)]
+        args: AdminArgs,
+    },
+    /// Compare the contract with Duolingo and the registry for every
+    /// registered UID, and optionally fix what has drifted
+    Reconcile {
+        /// Fix what can be fixed without a person deciding.
+        #[clap(long)]
+        fix: bool,
+
+        /// Only report XP that's at least this far ahead of the contract.
+        #[clap(long, default_value_t = reconcile::DEFAULT_XP_THRESHOLD)]
+        xp_threshold: u64,
+
+        /// Where to start indexing if nothing has been indexed for this
+        /// contract yet.
+        #[clap(long, default_value_t = 0)]
+        from_block: u64,
+
+        #[clap(flatten)]
+        args: AdminArgs,
+    },
+    /// Put an address in the Duolingo bio of the account a JWT belongs to
+    UpdateProfile {
+        address: Address,
+
+        #[clap(short, long, env = "DUOPOW_JWT")]
+        jwt: String,
+
+        #[clap(flatten)]
+        config: ConfigArgs,
+
+        #[clap(flatten)]
+        duolingo: DuolingoArgs,
+
+        #[clap(flatten)]
+        output: OutputArgs,
+    },
+}
+
+#[derive(clap::Args)]
+pub struct AdminArgs {
+    #[clap(flatten)]
+    config: ConfigArgs,
+
+    #[clap(flatten)]
+    chain: ChainArgs,
+
+    #[clap(flatten)]
+    duolingo: DuolingoArgs,
+
+    #[clap(flatten)]
+    storage: StorageArgs,
+
+    #[clap(flatten)]
+    output: OutputArgs,
+}
+
+impl AdminArgs {
+    async fn connect(self) -> anyhow::Result<(Connections, OutputArgs)> {
+        let (connections, _, output) = self.connect_with_chain().await?;
+        Ok((connections, output))
+    }
+
+    async fn connect_with_chain(self) -> anyhow::Result<(Connections, ChainConfig, OutputArgs)> {
+        let file = self.config.read()?;
+        let chain = ChainConfig::merge(self.chain, file.chain)?;
+        let duolingo = DuolingoConfig::merge(self.duolingo, file.duolingo)?;
+        let storage = StorageConfig::merge(self.storage, file.storage);
+
+        Ok((
+            connect(&chain, duolingo, &storage, None).await?,
+            chain,
+            self.output,
+        ))
+    }
+}
+
+#[derive(clap::Args)]
+pub struct OutputArgs {
+    /// Print JSON instead of text
+    #[clap(long)]
+    json: bool,
+}
+
+impl OutputArgs {
+    fn print<T: Serialize + fmt::Display>(&self, report: &T) -> anyhow::Result<()> {
+        print!("{}", self.render(report)?);
+        Ok(())
+    }
+
+    fn render<T: Serialize + fmt::Display>(&self, report: &T) -> anyhow::Result<String> {
+        Ok(if self.json {
+            serde_json::to_string_pretty(report)? + "\n"
+        } else {
+            report.to_string()
+        })
+    }
+}
+
+pub async fn run(command: AdminCommand) -> anyhow::Result<()> {
+    match command {
+        AdminCommand::Register { username, args } => {
+            let (connections, output) = args.connect().await?;
+            register(&connections, username).await?.print(&output)
+        }
+        AdminCommand::Unregister { username, args } => {
+            let (connections, output) = args.connect().await?;
+            unregister(&connections, username).await?.print(&output)
+        }
+        AdminCommand::UpdateAddress {
+            username,
+            address,
+            args,
+        } => {
+            let (connections, output) = args.connect().await?;
+            update_address(&connections, username, address)
+                .await?
+                .print(&output)
+        }
+        AdminCommand::ReportXp { username, args } => {
+            let (connections, output) = args.connect().await?;
+            report_xp(&connections, username).await?.print(&output)
+        }
+        AdminCommand::ShowUser { username, args } => {
+            let (connections, output) = args.connect().await?;
+            output.print(&show_user(&connections, username).await?)
+        }
+        AdminCommand::Index { from_block, args } => {
+            let (connections, chain, output) = args.connect_with_chain().await?;
+            let events = indexer::catch_up(&connections, from_block, chain.confirmations).await?;
+
+            output.print(&IndexReport {
+                events,
+                next_block: connections
+                    .registry
+                    .index_cursor(connections.contract_address)
+                    .await?
+                    .unwrap_or(from_block),
+                registered_users: connections.registry.indexed_users().await?.len(),
+            })
+        }
+        AdminCommand::Reconcile {
+            fix,
+            xp_threshold,
+            from_block,
+            args,
+        } => {
+            let (connections, chain, output) = args.connect_with_chain().await?;
+            indexer::catch_up(&connections, from_block, chain.confirmations).await?;
+            if fix {
+                ensure_owner_idle(&connections).await?;
+            }
+
+            output.print(&reconcile::reconcile(&connections, fix, xp_threshold).await?)
+        }
+        AdminCommand::UpdateProfile {
+            address,
+            jwt,
+            config,
+            duolingo,
+            output,
+        } => {
+            let file = config.read()?;
+            let duolingo = duolingo_client(DuolingoConfig::merge(duolingo, file.duolingo)?)?;
+
+            let uid = jwt::parse(&jwt)?.sub;
+            add_address_to_profile(&duolingo, uid, &jwt, address).await?;
+
+            output.print(&ProfileReport { uid, address })
+        }
+    }
+}
+
+pub async fn register(connections: &Connections, username: String) -> anyhow::Result<TxReport> {
+    let (uid, address) = find_address(connections, &username).await?;
+    let (address_in_contract, _) = connections.contract.users(uid.into()).await?;
+
+    Ok(if address_in_contract.is_zero() {
+        if connections.registry.account(uid).await?.is_none() {
+            eprintln!(
+                "Warning: {username} hasn't linked a Telegram account, so they won't be told \
+                 about their rewards until they /link"
+            );
+            connections.registry.add_unlinked(uid, &username).await?;
+        }
+
+        let xp = connections.duolingo.get_user_total_xp(uid).await?;
+        ensure_owner_idle(connections).await?;
+        TxReport {
+            xp: Some(xp),
+            ..TxReport::new("register", uid, username, Some(address))
+                .with(connections.register(uid, address, xp).await?)
+        }
+    } else if address_in_contract != address {
+        ensure_owner_idle(connections).await?;
+        TxReport::new("update-address", uid, username, Some(address))
+            .with(connections.update_address(uid, address).await?)
+    } else {
+        TxReport::new("register", uid, username, Some(address)).skipped("Already registered")
+    })
+}
+
+pub async fn unregister(connections: &Connections, username: String) -> anyhow::Result<TxReport> {
+    let uid = find_uid(connections, &username).await?;
+    let (address_in_contract, _) = connections.contract.users(uid.into()).await?;
+
+    let report = TxReport::new("unregister", uid, username, None);
+    Ok(if address_in_contract.is_zero() {
+        report.skipped("Not registered")
+    } else {
+        ensure_owner_idle(connections).await?;
+        report.with(connections.unregister(uid).await?)
+    })
+}
+
+pub async fn update_address(
+    connections: &Connections,
+    username: String,
+    address: Option<Address>,
+) -> anyhow::Result<TxReport> {
+    let (uid, address) = match address {
+        Some(address) => (find_uid(connections, &username).await?, address),
+        None => find_address(connections, &username).await?,
+    };
+    let (address_in_contract, _) = connections.contract.users(uid.into()).await?;
+
+    let report = TxReport::new("update-address", uid, username, Some(address));
+    Ok(if address_in_contract.is_zero() {
+        bail!(
+            "{} is not registered, use `register` instead",
+            report.username
+        );
+    } else if address_in_contract == address {
+        report.skipped("The address is unchanged")
+    } else {
+        ensure_owner_idle(connections).await?;
+        report.with(connections.update_address(uid, address).await?)
+    })
+}
+
+pub async fn report_xp(connections: &Connections, username: String) -> anyhow::Result<TxReport> {
+    let uid = find_uid(connections, &username).await?;
+    let total_xp = connections.duolingo.get_user_total_xp(uid).await?;
+    let (address_in_contract, xp_in_contract) = connections.contract.users(uid.into()).await?;
+
+    let report = TxReport {
+        xp: Some(total_xp),
+        ..TxReport::new("report-xp", uid, username, None)
+    };
+    Ok(if address_in_contract.is_zero() {
+        report.skipped("Not registered")
+    } else {
+        match XpProgress::new(total_xp, xp_in_contract) {
+            XpProgress::Gained(_) => {
+                ensure_owner_idle(connections).await?;
+                report.with(connections.report_xp(uid, total_xp).await?)
+            }
+            XpProgress::Unchanged => report.skipped("No new XP"),
+            XpProgress::Decreased(xp) => {
+                report.skipped(&format!("XP is {xp} below the last report"))
+            }
+        }
+    })
+}
+
+/// Admin commands send through a transaction queue of their own, which
+/// knows nothing about the nonces a running bot has handed out. Transactions
+/// waiting in the mempool mean something else is sending from the owner
+/// wallet right now.
+async fn ensure_owner_idle(connections: &Connections) -> anyhow::Result<()> {
+    let client = connections.contract.client();
+    let owner = client.address();
+
+    let latest = client
+        .get_transaction_count(owner, Some(BlockNumber::Latest.into()))
+        .await?;
+    let pending = client
+        .get_transaction_count(owner, Some(BlockNumber::Pending.into()))
+        .await?;
+
+    if pending > latest {
+        bail!(
+            "The owner wallet has {} transactions waiting to be mined. Admin commands can't send \
+             while the bot is sending from the same wallet, so stop it or try again once they're \
+             confirmed.",
+            pending - latest
+        );
+    }
+
+    Ok(())
+}
+
+async fn find_uid(connections: &Connections, username: &str) -> anyhow::Result<u64> {
+    Ok(find_address_maybe(connections, username).await?.0)
+}
+
+async fn find_address(connections: &Connections, username: &str) -> anyhow::Result<(u64, Address)> {
+    match find_address_maybe(connections, username).await? {
+        (uid, Some(address)) => Ok((uid, address)),
+        (_, None) => bail!(
+            "{username} has no address in their Duolingo bio and hasn't verified one with /linkwallet"
+        ),
+    }
+}
+
+async fn find_address_maybe(
+    connections: &Connections,
+    username: &str,
+) -> anyhow::Result<(u64, Option<Address>)> {
+    find_account(connections, username)
+        .await?
+        .ok_or_else(|| anyhow!("There's no Duolingo user called {username}"))
+}
+
+#[derive(Serialize)]
+pub struct TxReport {
+    pub action: &'static str,
+    uid: u64,
+    username: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    address: Option<Address>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    xp: Option<u64>,
+    #[serde(flatten)]
+    pub result: TxResult,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum TxResult {
+    Confirmed {
+        tx_hash: TxHash,
+    },
+    Reverted {
+        reason: Option<String>,
+        tx_hash: Option<TxHash>,
+    },
+    /// Nothing needed to be sent.
+    Skipped {
+        reason: String,
+    },
+}
+
+impl TxReport {
+    fn new(action: &'static str, uid: u64, username: String, address: Option<Address>) -> Self {
+        Self {
+            action,
+            uid,
+            username,
+            address,
+            xp: None,
+            result: TxResult::Skipped {
+                reason: String::new(),
+            },
+        }
+    }
+
+    fn with(self, outcome: TxOutcome) -> Self {
+        let result = match outcome {
+            TxOutcome::Confirmed(receipt) => TxResult::Confirmed {
+                tx_hash: receipt.transaction_hash,
+            },
+            TxOutcome::Reverted { reason, tx_hash } => TxResult::Reverted { reason, tx_hash },
+        };
+
+        Self { result, ..self }
+    }
+
+    fn skipped(self, reason: &str) -> Self {
+        Self {
+            result: TxResult::Skipped {
+                reason: reason.to_string(),
+            },
+            ..self
+        }
+    }
+
+    /// Fails after printing if the transaction was reverted, so scripts can
+    /// tell from the exit code.
+    fn print(&self, output: &OutputArgs) -> anyhow::Result<()> {
+        output.print(self)?;
+
+        match &self.result {
+            TxResult::Reverted { reason, .. } => Err(anyhow!(explain_revert(reason.as_deref()))),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for TxReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} {} (uid {})", self.action, self.username, self.uid)?;
+        if let Some(address) = &self.address {
+            writeln!(f, "  address: {}", to_checksum(address, None))?;
+        }
+        if let Some(xp) = self.xp {
+            writeln!(f, "  xp: {xp}")?;
+        }
+        match &self.result {
+            TxResult::Confirmed { tx_hash } => writeln!(f, "  confirmed in {tx_hash:?}"),
+            TxResult::Reverted { reason, tx_hash } => {
+                write!(f, "  reverted: {}", explain_revert(reason.as_deref()))?;
+                match tx_hash {
+                    Some(tx_hash) => writeln!(f, " ({tx_hash:?})"),
+                    None => writeln!(f),
+                }
+            }
+            TxResult::Skipped { reason } => writeln!(f, "  nothing sent: {reason}"),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct UserReport {
+    uid: u64,
+    username: String,
+    total_xp: u64,
+    address_in_bio: Option<Address>,
+    verified_address: Option<Address>,
+    /// `None` if the user isn't registered with the contract.
+    registered_address: Option<Address>,
+    xp_in_contract: u64,
+    mintable_xp: u64,
+    telegram_user_id: Option<u64>,
+    linked_at: Option<String>,
+    last_tx_hash: Option<TxHash>,
+    /// Only known if the contract's events have been indexed.
+    minted: Option<String>,
+    history: Vec<HistoryEntry>,
+}
+
+#[derive(Serialize)]
+struct HistoryEntry {
+    block_number: u64,
+    tx_hash: TxHash,
+    kind: &'static str,
+    address: Address,
+    amount: Option<String>,
+}
+
+fn pod(amount: U256) -> String {
+    format_units(amount, "ether").unwrap_or_else(|_| amount.to_string())
+}
+
+async fn show_user(connections: &Connections, username: String) -> anyhow::Result<UserReport> {
+    let user = connections
+        .duolingo
+        .get_user_by_username(&username)
+        .await?
+        .ok_or_else(|| anyhow!("There's no Duolingo user called {username}"))?;
+    let uid = user.id;
+
+    let total_xp = connections.duolingo.get_user_total_xp(uid).await?;
+    let (address_in_contract, xp_in_contract) = connections.contract.users(uid.into()).await?;
+    let account = connections.registry.account(uid).await?;
+    let indexed = connections.registry.indexed_user(uid).await?;
+    let history = connections.registry.indexed_events(uid).await?;
+
+    Ok(UserReport {
+        uid,
+        username: user.username,
+        total_xp,
+        address_in_bio: crate::duolingo::ETH_ADDRESS
+            .find(&user.bio)
+            .and_then(|address| address.as_str().parse().ok()),
+        verified_address: account.as_ref().and_then(|a| a.verified_address),
+        registered_address: (!address_in_contract.is_zero()).then_some(address_in_contract),
+        xp_in_contract: xp_in_contract.as_u64(),
+        mintable_xp: match XpProgress::new(total_xp, xp_in_contract) {
+            XpProgress::Gained(xp) => xp,
+            XpProgress::Unchanged | XpProgress::Decreased(_) => 0,
+        },
+        telegram_user_id: account
+            .as_ref()
+            .and_then(|a| a.telegram_user_id)
+            .map(|id| id.0),
+        linked_at: account.as_ref().map(|a| a.linked_at.to_rfc3339()),
+        last_tx_hash: account.and_then(|a| a.last_tx_hash),
+        minted: indexed.map(|u| pod(u.minted)),
+        history: history
+            .into_iter()
+            .map(|event| HistoryEntry {
+                block_number: event.block_number,
+                tx_hash: event.tx_hash,
+                kind: event.kind.as_str(),
+                address: event.address,
+                amount: event.amount.map(pod),
+            })
+            .collect(),
+    })
+}
+
+impl fmt::Display for UserReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn address(address: &Option<Address>) -> String {
+            address
+                .map(|a| to_checksum(&a, None))
+                .unwrap_or_else(|| "none".to_string())
+        }
+
+        writeln!(f, "{} (uid {})", self.username, self.uid)?;
+        writeln!(f, "  Duolingo XP: {}", self.total_xp)?;
+        writeln!(f, "  address in bio: {}", address(&self.address_in_bio))?;
+        writeln!(f, "  verified address: {}", address(&self.verified_address))?;
+        match &self.registered_address {
+            Some(_) => writeln!(
+                f,
+                "  registered: {} with {} XP, {} more to mint",
+                address(&self.registered_address),
+                self.xp_in_contract,
+                self.mintable_xp
+            )?,
+            None => writeln!(f, "  registered: no")?,
+        }
+        match (self.telegram_user_id, &self.linked_at) {
+            (Some(id), Some(linked_at)) => {
+                writeln!(f, "  linked to Telegram user {id} since {linked_at}")?
+            }
+            _ => writeln!(f, "  not linked to a Telegram user")?,
+        }
+        if let Some(tx_hash) = &self.last_tx_hash {
+            writeln!(f, "  last transaction: {tx_hash:?}")?;
+        }
+        if let Some(minted) = &self.minted {
+            writeln!(f, "  minted in total: {minted} POD")?;
+        }
+        for entry in &self.history {
+            write!(
+                f,
+                "  block {}: {} {}",
+                entry.block_number,
+                entry.kind,
+                to_checksum(&entry.address, None)
+            )?;
+            match &entry.amount {
+                Some(amount) => writeln!(f, " {amount} POD ({:?})", entry.tx_hash)?,
+                None => writeln!(f, " ({:?})", entry.tx_hash)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Serialize)]
+struct IndexReport {
+    events: usize,
+    next_block: u64,
+    registered_users: usize,
+}
+
+impl fmt::Display for IndexReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Indexed {} new events up to block {}; {} UIDs are registered",
+            self.events,
+            self.next_block.saturating_sub(1),
+            self.registered_users
+        )
+    }
+}
+
+#[derive(Serialize)]
+struct ProfileReport {
+    uid: u64,
+    address: Address,
+}
+
+impl fmt::Display for ProfileReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Added {} to the bio of Duolingo user {}",
+            to_checksum(&self.address, None),
+            self.uid
+        )
+    }
+}
+
+#[test]
+fn test_tx_report_output() {
+    let text = OutputArgs { json: false };
+    let json = OutputArgs { json: true };
+    let address: Address = "0x69AA0361Dbb0527d4F1e5312403Bd41788fe61Fe"
+        .parse()
+        .unwrap();
+    let report = || TxReport {
+        xp: Some(100),
+        ..TxReport::new("register", 1001, "alice".to_string(), Some(address))
+    };
+
+    let confirmed = TxReport {
+        result: TxResult::Confirmed {
+            tx_hash: TxHash::repeat_byte(1),
+        },
+        ..report()
+    };
+    assert_eq!(
+        text.render(&confirmed).unwrap(),
+        format!(
+            "register alice (uid 1001)\n  address: 0x69AA0361Dbb0527d4F1e5312403Bd41788fe61Fe\n  xp: 100\n  confirmed in {:?}\n",
+            TxHash::repeat_byte(1)
+        )
+    );
+    assert_eq!(
+        serde_json::from_str::<serde_json::Value>(&json.render(&confirmed).unwrap()).unwrap(),
+        serde_json::json!({
+            "action": "register",
+            "uid": 1001,
+            "username": "alice",
+            "address": "0x69aa0361dbb0527d4f1e5312403bd41788fe61fe",
+            "xp": 100,
+            "status": "confirmed",
+            "tx_hash": format!("{:?}", TxHash::repeat_byte(1)),
+        })
+    );
+    assert!(confirmed.print(&json).is_ok());
+
+    let skipped =
+        TxReport::new("unregister", 1001, "alice".to_string(), None).skipped("Not registered");
+    assert_eq!(
+        text.render(&skipped).unwrap(),
+        "unregister alice (uid 1001)\n  nothing sent: Not registered\n"
+    );
+    assert!(skipped.print(&text).is_ok());
+
+    // scripts can tell a revert from the exit code
+    let reverted = report().with(TxOutcome::Reverted {
+        reason: Some("Address is already registered".to_string()),
+        tx_hash: None,
+    });
+    assert!(text.render(&reverted).unwrap().ends_with(
+        "  reverted: That address is already registered to another Duolingo account.\n"
+    ));
+    assert_eq!(
+        serde_json::from_str::<serde_json::Value>(&json.render(&reverted).unwrap()).unwrap()
+            ["reason"],
+        "Address is already registered"
+    );
+    assert_eq!(
+        reverted.print(&json).unwrap_err().to_string(),
+        "That address is already registered to another Duolingo account."
+    );
+}
+
+#[test]
+fn test_user_report_output() {
+    let address: Address = "0x69AA0361Dbb0527d4F1e5312403Bd41788fe61Fe"
+        .parse()
+        .unwrap();
+    let report = UserReport {
+        uid: 1001,
+        username: "alice".to_string(),
+        total_xp: 130,
+        address_in_bio: Some(address),
+        verified_address: None,
+        registered_address: Some(address),
+        xp_in_contract: 100,
+        mintable_xp: 30,
+        telegram_user_id: None,
+        linked_at: None,
+        last_tx_hash: None,
+        minted: Some("100.000000000000000000".to_string()),
+        history: vec![HistoryEntry {
+            block_number: 10,
+            tx_hash: TxHash::repeat_byte(1),
+            kind: "registered",
+            address,
+            amount: None,
+        }],
+    };
+
+    assert_eq!(
+        OutputArgs { json: false }.render(&report).unwrap(),
+        format!(
+            "alice (uid 1001)
+  Duolingo XP: 130
+  address in bio: 0x69AA0361Dbb0527d4F1e5312403Bd41788fe61Fe
+  verified address: none
+  registered: 0x69AA0361Dbb0527d4F1e5312403Bd41788fe61Fe with 100 XP, 30 more to mint
+  not linked to a Telegram user
+  minted in total: 100.000000000000000000 POD
+  block 10: registered 0x69AA0361Dbb0527d4F1e5312403Bd41788fe61Fe ({:?})
+",
+            TxHash::repeat_byte(1)
+        )
+    );
+
+    let json: serde_json::Value =
+        serde_json::from_str(&OutputArgs { json: true }.render(&report).unwrap()).unwrap();
+    assert_eq!(json["mintable_xp"], 30);
+    assert_eq!(json["verified_address"], serde_json::Value::Null);
+    assert_eq!(json["history"][0]["kind"], "registered");
+}
@@ -0,0 +1,132 @@
+use std::{sync::Arc, time::Duration};
+
+use ethers::{
+    providers::Middleware,
+    types::{Address, U256},
+    utils::{format_units, to_checksum},
+};
+use teloxide::{prelude::*, types::ParseMode};
+use tokio::time::MissedTickBehavior;
+
+use crate::{metrics, Connections, OwnerContract};
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+pub async fn owner_balance(contract: &OwnerContract) -> anyhow::Result<U256> {
+    let client = contract.client();
+    Ok(client.get_balance(client.address(), None).await?)
+}
+
+/// Checks the owner balance every minute, and tells `alert_chat` when it
+/// drops below `min_balance` and again once it has been topped up.
+pub fn spawn(
+    bot: Bot,
+    connections: Arc<Connections>,
+    min_balance: U256,
+    alert_chat: Option<ChatId>,
+) {
+    tokio::spawn(async move {
+        let owner = connections.contract.client().address();
+        let mut monitor = Monitor {
+            owner,
+            min_balance,
+            low: false,
+        };
+
+        let mut ticker = tokio::time::interval(CHECK_INTERVAL);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            ticker.tick().await;
+
+            let balance = match owner_balance(&connections.contract).await {
+                Ok(balance) => balance,
+                Err(e) => {
+                    log::warn!("Failed to fetch the owner balance: {e:#}");
+                    continue;
+                }
+            };
+            metrics::owner_balance(balance);
+
+            let Some(alert) = monitor.check(balance) else {
+                continue;
+            };
+            log::warn!("{alert}");
+
+            if let Some(chat_id) = alert_chat {
+                if let Err(e) = bot
+                    .send_message(chat_id, alert)
+                    .parse_mode(ParseMode::Html)
+                    .await
+                {
+                    log::error!("Failed to send a balance alert: {e}");
+                }
+            }
+        }
+    });
+}
+
+struct Monitor {
+    owner: Address,
+    min_balance: U256,
+    low: bool,
+}
+
+impl Monitor {
+    /// Returns an alert if the balance has crossed the minimum since the last
+    /// check.
+    fn check(&mut self, balance: U256) -> Option<String> {
+        let low = balance < self.min_balance;
+        if low == self.low {
+            return None;
+        }
+        self.low = low;
+
+        let owner = to_checksum(&self.owner, None);
+        Some(if low {
+            format!(
+                "The owner wallet <code>{owner}</code> has {} ETH left, below the minimum of {} ETH. \
+                 Transactions are on hold until it's topped up.",
+                ether(balance),
+                ether(self.min_balance)
+            )
+        } else {
+            format!(
+                "The owner wallet <code>{owner}</code> has been topped up to {} ETH. \
+                 Transactions are back on.",
+                ether(balance)
+            )
+        })
+    }
+}
+
+fn ether(amount: U256) -> String {
+    format_units(amount, "ether").unwrap_or_else(|_| amount.to_string())
+}
+
+#[test]
+fn test_monitor_alerts_when_crossing_the_minimum() {
+    let mut monitor = Monitor {
+        owner: Address::zero(),
+        min_balance: U256::exp10(16),
+        low: false,
+    };
+
+    assert_eq!(monitor.check(U256::exp10(17)), None);
+
+    let alert = monitor.check(U256::exp10(15)).unwrap();
+    assert!(
+        alert.contains("has 0.001000000000000000 ETH left"),
+        "{alert}"
+    );
+    assert!(
+        alert.contains("minimum of 0.010000000000000000 ETH"),
+        "{alert}"
+    );
+    // only once, not on every check while it stays low
+    assert_eq!(monitor.check(U256::exp10(15)), None);
+
+    let alert = monitor.check(U256::exp10(16)).unwrap();
+    assert!(alert.contains("topped up"), "{alert}");
+    assert_eq!(monitor.check(U256::exp10(16)), None);
+}
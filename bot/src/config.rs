@@ -0,0 +1,632 @@
+use std::{net::SocketAddr, path::PathBuf, time::Duration};
+
+use anyhow::{anyhow, bail, Context};
+use ethers::{
+    types::{Address, U256},
+    utils::parse_units,
+};
+use reqwest::Url;
+use serde::Deserialize;
+use teloxide::types::{ChatId, UserId};
+
+use crate::{duolingo, storage::StorageKind, tx, webhook, USER_AGENT};
+
+// Where to read settings from, shared by every subcommand that has any.
+#[derive(clap::Args, Default)]
+pub struct ConfigArgs {
+    /// A TOML file with any of the settings below. Flags override their
+    /// `DUOPOW_*` environment variable, which overrides the file.
+    #[clap(long, env = "DUOPOW_CONFIG")]
+    pub config: Option<PathBuf>,
+}
+
+impl ConfigArgs {
+    pub fn read(&self) -> anyhow::Result<ConfigFile> {
+        let Some(path) = &self.config else {
+            return Ok(ConfigFile::default());
+        };
+
+        let contents = std::fs::read_to_string(path).with_context(|| {
+            format!("Failed to read the config file {}", path.to_string_lossy())
+        })?;
+
+        toml::from_str(&contents)
+            .with_context(|| format!("Invalid config file {}", path.to_string_lossy()))
+    }
+}
+
+#[derive(clap::Args, Default)]
+pub struct TelegramArgs {
+    #[clap(short, long, env = "DUOPOW_TG_TOKEN")]
+    pub tg_token: Option<String>,
+
+    /// Receive updates through a webhook at this public URL instead of long
+    /// polling.
+    #[clap(long, env = "DUOPOW_WEBHOOK_URL")]
+    pub webhook_url: Option<Url>,
+
+    /// Where to serve the webhook, usually behind a reverse proxy that
+    /// forwards `--webhook-url` here. Defaults to 127.0.0.1:8443.
+    #[clap(long, env = "DUOPOW_LISTEN_ADDR")]
+    pub listen_addr: Option<SocketAddr>,
+
+    /// Telegram sends this with every update so forged ones can be rejected.
+    /// Random if unset.
+    #[clap(long, env = "DUOPOW_WEBHOOK_SECRET", value_parser = webhook::parse_secret)]
+    pub webhook_secret: Option<String>,
+
+    /// Telegram user IDs that may use admin commands like /reconcile.
+    #[clap(long = "admin", env = "DUOPOW_ADMINS", value_delimiter = ',')]
+    pub admins: Vec<u64>,
+
+    /// A chat to alert when the owner wallet runs low.
+    #[clap(long, env = "DUOPOW_ALERT_CHAT")]
+    pub alert_chat: Option<i64>,
+}
+
+#[derive(clap::Args, Default)]
+pub struct ChainArgs {
+    #[clap(short, long, env = "DUOPOW_KEYSTORE")]
+    pub keystore: Option<PathBuf>,
+
+    #[clap(short, long, env = "DUOPOW_PASSWORD")]
+    pub password: Option<String>,
+
+    #[clap(short, long, env = "DUOPOW_CONTRACT")]
+    pub contract: Option<Address>,
+
+    #[clap(short, long, env = "DUOPOW_RPC")]
+    pub rpc: Option<Url>,
+
+    #[clap(long, env = "DUOPOW_CONFIRMATIONS")]
+    pub confirmations: Option<usize>,
+
+    #[clap(long, env = "DUOPOW_TX_RETRIES")]
+    pub tx_retries: Option<usize>,
+
+    /// Don't send owner transactions while the gas price is above this many
+    /// gwei.
+    #[clap(long, env = "DUOPOW_MAX_GAS_PRICE")]
+    pub max_gas_price: Option<f64>,
+
+    /// Don't send owner transactions while the owner wallet has less than
+    /// this much ether.
+    #[clap(long, env = "DUOPOW_MIN_BALANCE")]
+    pub min_balance: Option<f64>,
+
+    #[clap(long, env = "DUOPOW_EXPLORER_URL")]
+    pub explorer_url: Option<Url>,
+}
+
+#[derive(clap::Args, Default)]
+pub struct DuolingoArgs {
+    #[clap(long, env = "DUOPOW_DUOLINGO_BASE_URL")]
+    pub duolingo_base_url: Option<Url>,
+
+    /// Seconds to wait for Duolingo before giving up on a request.
+    #[clap(long, env = "DUOPOW_DUOLINGO_TIMEOUT")]
+    pub duolingo_timeout: Option<u64>,
+
+    #[clap(long, env = "DUOPOW_DUOLINGO_USER_AGENT")]
+    pub duolingo_user_agent: Option<String>,
+
+    /// Space out Duolingo requests so there are at most this many a second.
+    #[clap(long, env = "DUOPOW_DUOLINGO_RATE_LIMIT")]
+    pub duolingo_rate_limit: Option<u32>,
+}
+
+#[derive(clap::Args, Default)]
+pub struct StorageArgs {
+    #[clap(long, env = "DUOPOW_STORAGE", value_enum)]
+    pub storage: Option<StorageKind>,
+
+    #[clap(long, env = "DUOPOW_DB")]
+    pub db: Option<PathBuf>,
+}
+
+#[derive(clap::Args, Default)]
+pub struct FeaturesArgs {
+    /// Collect XP reports for this many seconds and send them in one
+    /// transaction. Reports are sent immediately when unset.
+    #[clap(long, env = "DUOPOW_XP_BATCH_WINDOW")]
+    pub xp_batch_window: Option<u64>,
+
+    /// Report XP for every registered account this often, in seconds.
+    #[clap(long, env = "DUOPOW_SYNC_INTERVAL")]
+    pub sync_interval: Option<u64>,
+
+    /// Message users when the sync mints POD for them.
+    #[clap(long, env = "DUOPOW_SYNC_NOTIFY", num_args = 0..=1, default_missing_value = "true")]
+    pub sync_notify: Option<bool>,
+
+    /// Serve Prometheus metrics at `/metrics` and a health check at
+    /// `/healthz` on this address.
+    #[clap(long, env = "DUOPOW_METRICS_ADDR")]
+    pub metrics_addr: Option<SocketAddr>,
+
+    /// Index the contract's events from this block on, e.g. the block it was
+    /// deployed in.
+    #[clap(long, env = "DUOPOW_INDEX_FROM_BLOCK")]
+    pub index_from_block: Option<u64>,
+}
+
+#[derive(clap::Args, Default)]
+pub struct RunArgs {
+    #[clap(flatten)]
+    pub config: ConfigArgs,
+
+    #[clap(flatten)]
+    pub telegram: TelegramArgs,
+
+    #[clap(flatten)]
+    pub chain: ChainArgs,
+
+    #[clap(flatten)]
+    pub duolingo: DuolingoArgs,
+
+    #[clap(flatten)]
+    pub storage: StorageArgs,
+
+    #[clap(flatten)]
+    pub features: FeaturesArgs,
+}
+
+/// The layout of the config file. Everything is optional here so that it can
+/// be filled in from the environment or flags instead.
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfigFile {
+    pub telegram: TelegramFile,
+    pub chain: ChainFile,
+    pub duolingo: DuolingoFile,
+    pub storage: StorageFile,
+    pub features: FeaturesFile,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct TelegramFile {
+    token: Option<String>,
+    webhook_url: Option<Url>,
+    listen_addr: Option<SocketAddr>,
+    webhook_secret: Option<String>,
+    admins: Option<Vec<u64>>,
+    alert_chat: Option<i64>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChainFile {
+    rpc: Option<Url>,
+    contract: Option<Address>,
+    keystore: Option<PathBuf>,
+    password: Option<String>,
+    confirmations: Option<usize>,
+    tx_retries: Option<usize>,
+    max_gas_price: Option<f64>,
+    min_balance: Option<f64>,
+    explorer_url: Option<Url>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct DuolingoFile {
+    base_url: Option<Url>,
+    timeout: Option<u64>,
+    user_agent: Option<String>,
+    rate_limit: Option<u32>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageFile {
+    kind: Option<StorageKind>,
+    db: Option<PathBuf>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct FeaturesFile {
+    xp_batch_window: Option<u64>,
+    sync_interval: Option<u64>,
+    sync_notify: Option<bool>,
+    metrics_addr: Option<SocketAddr>,
+    index_from_block: Option<u64>,
+}
+
+/// Settings for `run` with every layer applied.
+#[derive(Debug)]
+pub struct Config {
+    pub telegram: TelegramConfig,
+    pub chain: ChainConfig,
+    pub duolingo: DuolingoConfig,
+    pub storage: StorageConfig,
+    pub features: Features,
+}
+
+#[derive(Debug)]
+pub struct TelegramConfig {
+    pub token: String,
+    /// Long polling is used when unset.
+    pub webhook: Option<WebhookConfig>,
+    pub admins: Vec<UserId>,
+    pub alert_chat: Option<ChatId>,
+}
+
+#[derive(Debug)]
+pub struct WebhookConfig {
+    pub url: Url,
+    pub listen_addr: SocketAddr,
+    pub secret: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct ChainConfig {
+    pub rpc: Url,
+    contract: Option<Address>,
+    pub keystore: PathBuf,
+    pub password: String,
+    pub confirmations: usize,
+    pub tx_retries: usize,
+    /// In wei.
+    pub max_gas_price: Option<U256>,
+    pub min_balance: Option<U256>,
+    pub explorer_url: Url,
+}
+
+#[derive(Debug)]
+pub struct DuolingoConfig {
+    pub base_url: Url,
+    pub timeout: Duration,
+    pub user_agent: String,
+    pub rate_limit: Option<u32>,
+}
+
+#[derive(Debug)]
+pub struct StorageConfig {
+    pub kind: StorageKind,
+    pub db: PathBuf,
+}
+
+#[derive(Debug)]
+pub struct Features {
+    pub xp_batch_window: Option<Duration>,
+    pub sync_interval: Option<Duration>,
+    pub sync_notify: bool,
+    pub metrics_addr: Option<SocketAddr>,
+    pub index_from_block: Option<u64>,
+}
+
+const DEFAULT_LISTEN_ADDR: &str = "127.0.0.1:8443";
+const DEFAULT_DUOLINGO_TIMEOUT: Duration = Duration::from_secs(30);
+
+impl Config {
+    pub fn load(args: RunArgs) -> anyhow::Result<Self> {
+        let file = args.config.read()?;
+
+        Self::merge(args, file)
+    }
+
+    fn merge(args: RunArgs, file: ConfigFile) -> anyhow::Result<Self> {
+        let config = Self {
+            telegram: TelegramConfig::merge(args.telegram, file.telegram)?,
+            chain: ChainConfig::merge(args.chain, file.chain)?,
+            duolingo: DuolingoConfig::merge(args.duolingo, file.duolingo)?,
+            storage: StorageConfig::merge(args.storage, file.storage),
+            features: Features::merge(args.features, file.features)?,
+        };
+        config.chain.contract()?;
+
+        // the balance is only watched with a minimum to compare it to
+        if config.telegram.alert_chat.is_some() && config.chain.min_balance.is_none() {
+            bail!(
+                "`telegram.alert_chat` is set, but there's no `chain.min_balance` to alert about"
+            );
+        }
+
+        Ok(config)
+    }
+}
+
+impl TelegramConfig {
+    pub fn merge(args: TelegramArgs, file: TelegramFile) -> anyhow::Result<Self> {
+        let webhook_secret = args
+            .webhook_secret
+            .or(file.webhook_secret)
+            .map(|secret| {
+                webhook::parse_secret(&secret).map_err(|e| anyhow!("`telegram.webhook_secret` {e}"))
+            })
+            .transpose()?;
+        let listen_addr = args.listen_addr.or(file.listen_addr);
+        let webhook = match args.webhook_url.or(file.webhook_url) {
+            Some(url) => Some(WebhookConfig {
+                url,
+                listen_addr: listen_addr.unwrap_or_else(|| DEFAULT_LISTEN_ADDR.parse().unwrap()),
+                secret: webhook_secret,
+            }),
+            None if listen_addr.is_some() || webhook_secret.is_some() => bail!(
+                "`telegram.listen_addr` and `telegram.webhook_secret` only apply to webhooks, \
+                 but `telegram.webhook_url` is not set"
+            ),
+            None => None,
+        };
+
+        Ok(Self {
+            token: required(
+                args.tg_token.or(file.token),
+                "telegram.token",
+                "--tg-token",
+                "DUOPOW_TG_TOKEN",
+            )?,
+            webhook,
+            admins: if args.admins.is_empty() {
+                file.admins.unwrap_or_default()
+            } else {
+                args.admins
+            }
+            .into_iter()
+            .map(UserId)
+            .collect(),
+            alert_chat: args.alert_chat.or(file.alert_chat).map(ChatId),
+        })
+    }
+}
+
+impl ChainConfig {
+    pub fn merge(args: ChainArgs, file: ChainFile) -> anyhow::Result<Self> {
+        let keystore = required(
+            args.keystore.or(file.keystore),
+            "chain.keystore",
+            "--keystore",
+            "DUOPOW_KEYSTORE",
+        )?;
+        if !keystore.is_file() {
+            bail!(
+                "`chain.keystore`: {} is not a file",
+                keystore.to_string_lossy()
+            );
+        }
+
+        let max_gas_price = args
+            .max_gas_price
+            .or(file.max_gas_price)
+            .map(|gwei| {
+                if !(gwei.is_finite() && gwei > 0.0) {
+                    bail!("`chain.max_gas_price` must be a positive number of gwei");
+                }
+                Ok(U256::from(parse_units(gwei.to_string(), "gwei")?))
+            })
+            .transpose()?;
+        let min_balance = args
+            .min_balance
+            .or(file.min_balance)
+            .map(|ether| {
+                if !(ether.is_finite() && ether > 0.0) {
+                    bail!("`chain.min_balance` must be a positive amount of ether");
+                }
+                Ok(U256::from(parse_units(ether.to_string(), "ether")?))
+            })
+            .transpose()?;
+
+        Ok(Self {
+            rpc: required(args.rpc.or(file.rpc), "chain.rpc", "--rpc", "DUOPOW_RPC")?,
+            contract: args.contract.or(file.contract),
+            keystore,
+            password: args.password.or(file.password).unwrap_or_default(),
+            confirmations: args.confirmations.or(file.confirmations).unwrap_or(1),
+            tx_retries: args.tx_retries.or(file.tx_retries).unwrap_or(3),
+            max_gas_price,
+            min_balance,
+            explorer_url: args
+                .explorer_url
+                .or(file.explorer_url)
+                .unwrap_or_else(|| tx::DEFAULT_EXPLORER_URL.parse().unwrap()),
+        })
+    }
+
+    /// The deployed contract. Only optional so that `deploy` can do without.
+    pub fn contract(&self) -> anyhow::Result<Address> {
+        required(
+            self.contract,
+            "chain.contract",
+            "--contract",
+            "DUOPOW_CONTRACT",
+        )
+    }
+}
+
+impl DuolingoConfig {
+    pub fn merge(args: DuolingoArgs, file: DuolingoFile) -> anyhow::Result<Self> {
+        let rate_limit = args.duolingo_rate_limit.or(file.rate_limit);
+        if rate_limit == Some(0) {
+            bail!("`duolingo.rate_limit` must be at least 1 request a second");
+        }
+
+        Ok(Self {
+            base_url: args
+                .duolingo_base_url
+                .or(file.base_url)
+                .unwrap_or_else(|| duolingo::DEFAULT_BASE_URL.parse().unwrap()),
+            timeout: seconds(args.duolingo_timeout.or(file.timeout), "duolingo.timeout")?
+                .unwrap_or(DEFAULT_DUOLINGO_TIMEOUT),
+            user_agent: args
+                .duolingo_user_agent
+                .or(file.user_agent)
+                .unwrap_or_else(|| USER_AGENT.to_string()),
+            rate_limit,
+        })
+    }
+}
+
+impl StorageConfig {
+    pub fn merge(args: StorageArgs, file: StorageFile) -> Self {
+        Self {
+            kind: args.storage.or(file.kind).unwrap_or(StorageKind::Sqlite),
+            db: args.db.or(file.db).unwrap_or_else(|| "db.sqlite".into()),
+        }
+    }
+}
+
+impl Features {
+    pub fn merge(args: FeaturesArgs, file: FeaturesFile) -> anyhow::Result<Self> {
+        Ok(Self {
+            xp_batch_window: seconds(
+                args.xp_batch_window.or(file.xp_batch_window),
+                "features.xp_batch_window",
+            )?,
+            sync_interval: seconds(
+                args.sync_interval.or(file.sync_interval),
+                "features.sync_interval",
+            )?,
+            sync_notify: args.sync_notify.or(file.sync_notify).unwrap_or(false),
+            metrics_addr: args.metrics_addr.or(file.metrics_addr),
+            index_from_block: args.index_from_block.or(file.index_from_block),
+        })
+    }
+}
+
+fn required<T>(value: Option<T>, key: &str, flag: &str, env: &str) -> anyhow::Result<T> {
+    value.ok_or_else(|| {
+        anyhow!("`{key}` is not set. Set it in the config file, with {flag} or with {env}")
+    })
+}
+
+fn seconds(value: Option<u64>, key: &str) -> anyhow::Result<Option<Duration>> {
+    match value {
+        Some(0) => bail!("`{key}` must be at least 1 second"),
+        value => Ok(value.map(Duration::from_secs)),
+    }
+}
+
+#[test]
+fn test_layering() {
+    let file: ConfigFile = toml::from_str(
+        r#"
+[telegram]
+token = "from-file"
+admins = [7001, 7002]
+
+[chain]
+rpc = "http://127.0.0.1:8545/"
+contract = "0x69AA0361Dbb0527d4F1e5312403Bd41788fe61Fe"
+keystore = "Cargo.toml"
+confirmations = 3
+max_gas_price = 0.5
+min_balance = 0.25
+
+[duolingo]
+timeout = 10
+
+[features]
+sync_interval = 3600
+sync_notify = true
+"#,
+    )
+    .unwrap();
+
+    let config = Config::merge(
+        RunArgs {
+            telegram: TelegramArgs {
+                tg_token: Some("from-flag".to_string()),
+                ..Default::default()
+            },
+            features: FeaturesArgs {
+                sync_notify: Some(false),
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+        file,
+    )
+    .unwrap();
+
+    assert_eq!(config.telegram.token, "from-flag");
+    assert!(config.telegram.webhook.is_none());
+    assert_eq!(config.telegram.admins, vec![UserId(7001), UserId(7002)]);
+    assert_eq!(config.chain.confirmations, 3);
+    assert_eq!(config.chain.tx_retries, 3);
+    assert_eq!(config.chain.max_gas_price, Some(500_000_000.into()));
+    assert_eq!(
+        config.chain.min_balance,
+        Some(U256::exp10(17) * 2 + U256::exp10(16) * 5)
+    );
+    assert_eq!(config.duolingo.timeout, Duration::from_secs(10));
+    assert_eq!(config.duolingo.user_agent, USER_AGENT);
+    assert_eq!(
+        config.features.sync_interval,
+        Some(Duration::from_secs(3600))
+    );
+    assert!(!config.features.sync_notify);
+}
+
+#[test]
+fn test_invalid_configs() {
+    fn error(toml: &str) -> String {
+        let file: ConfigFile = match toml::from_str(toml) {
+            Ok(file) => file,
+            Err(e) => return e.to_string(),
+        };
+        Config::merge(RunArgs::default(), file)
+            .unwrap_err()
+            .to_string()
+    }
+
+    let minimal = r#"
+[telegram]
+token = "token"
+
+[chain]
+rpc = "http://127.0.0.1:8545/"
+contract = "0x69AA0361Dbb0527d4F1e5312403Bd41788fe61Fe"
+keystore = "Cargo.toml"
+"#;
+    assert!(Config::merge(RunArgs::default(), toml::from_str(minimal).unwrap()).is_ok());
+
+    let e = error("[chain]\nrcp = \"http://127.0.0.1:8545/\"\n");
+    assert!(e.contains("line 2"), "{e}");
+    assert!(e.contains("unknown field `rcp`"), "{e}");
+
+    let e = error("[chain]\nconfirmations = \"one\"\n");
+    assert!(e.contains("line 2"), "{e}");
+    assert!(e.contains("invalid type"), "{e}");
+
+    let e = error("[telegram]\ntoken = \"token\"\n");
+    assert!(e.contains("`chain.keystore` is not set"), "{e}");
+    assert!(e.contains("DUOPOW_KEYSTORE"), "{e}");
+
+    let e = error(&format!("{minimal}\n[features]\nsync_interval = 0\n"));
+    assert!(e.contains("`features.sync_interval` must be at least 1 second"));
+
+    let e = error(&minimal.replace(
+        "token = \"token\"",
+        "token = \"token\"\nwebhook_secret = \"a b\"",
+    ));
+    assert!(e.contains("`telegram.webhook_secret` contains ' '"), "{e}");
+
+    let e = error(&format!("{minimal}min_balance = -1\n"));
+    assert!(
+        e.contains("`chain.min_balance` must be a positive amount of ether"),
+        "{e}"
+    );
+
+    let e = error(&minimal.replace(
+        "token = \"token\"",
+        "token = \"token\"\nalert_chat = -1001234567890",
+    ));
+    assert!(
+        e.contains("`telegram.alert_chat` is set, but there's no `chain.min_balance`"),
+        "{e}"
+    );
+    assert!(Config::merge(
+        RunArgs::default(),
+        toml::from_str(&format!(
+            "{}min_balance = 0.01\n",
+            minimal.replace(
+                "token = \"token\"",
+                "token = \"token\"\nalert_chat = -1001234567890"
+            )
+        ))
+        .unwrap()
+    )
+    .is_ok());
+}
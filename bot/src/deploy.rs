@@ -0,0 +1,195 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context};
+use ethers::{providers::Middleware, types::Address, utils::to_checksum};
+
+use crate::{
+    config::{ChainArgs, ChainConfig, ConfigArgs},
+    owner_client, DuolingoPowContract, OwnerContract,
+};
+
+#[derive(clap::Args)]
+pub struct DeployArgs {
+    #[clap(long, default_value = "Proof of Duolingo")]
+    name: String,
+
+    #[clap(long, default_value = "POD")]
+    symbol: String,
+
+    /// Set `DUOPOW_CONTRACT` in this `.env` file to the new address.
+    #[clap(long)]
+    write_env: Option<PathBuf>,
+
+    /// Set `chain.contract` in the `--config` file to the new address.
+    #[clap(long, requires = "config")]
+    write_config: bool,
+
+    #[clap(flatten)]
+    config: ConfigArgs,
+
+    #[clap(flatten)]
+    chain: ChainArgs,
+}
+
+/// Deploys `DuolingoPow` from the keystore wallet, which becomes its owner.
+pub async fn run(args: DeployArgs) -> anyhow::Result<()> {
+    let file = args.config.read()?;
+    let chain = ChainConfig::merge(args.chain, file.chain)?;
+    let client = owner_client(&chain).await?;
+
+    let (contract, receipt) =
+        DuolingoPowContract::deploy(client.clone(), (args.name.clone(), args.symbol.clone()))?
+            .confirmations(chain.confirmations)
+            .send_with_receipt()
+            .await
+            .context("Failed to deploy the contract")?;
+    let address = contract.address();
+
+    println!(
+        "Deployed {} ({}) to {} in {:?}",
+        args.name,
+        args.symbol,
+        to_checksum(&address, None),
+        receipt.transaction_hash
+    );
+
+    verify(&contract, &args.name, &args.symbol, client.address()).await?;
+
+    if let Some(path) = &args.write_env {
+        update_file(path, |contents| {
+            Ok(set_env_var(
+                contents,
+                "DUOPOW_CONTRACT",
+                &to_checksum(&address, None),
+            ))
+        })?;
+        println!("Set DUOPOW_CONTRACT in {}", path.to_string_lossy());
+    }
+
+    if let (true, Some(path)) = (args.write_config, &args.config.config) {
+        update_file(path, |contents| set_config_contract(contents, address))?;
+        println!("Set chain.contract in {}", path.to_string_lossy());
+    }
+
+    Ok(())
+}
+
+/// Checks that the code at the new address is the contract we meant to deploy.
+async fn verify(
+    contract: &OwnerContract,
+    name: &str,
+    symbol: &str,
+    owner: Address,
+) -> anyhow::Result<()> {
+    let code = contract.client().get_code(contract.address(), None).await?;
+    if code.is_empty() {
+        bail!("There's no code at the deployed address");
+    }
+
+    let deployed = (
+        contract.name().call().await?,
+        contract.symbol().call().await?,
+        contract.owner().call().await?,
+    );
+    if deployed != (name.to_string(), symbol.to_string(), owner) {
+        bail!(
+            "The deployed contract doesn't match: it's {} ({}) owned by {}",
+            deployed.0,
+            deployed.1,
+            to_checksum(&deployed.2, None)
+        );
+    }
+
+    Ok(())
+}
+
+fn update_file(
+    path: &Path,
+    update: impl FnOnce(&str) -> anyhow::Result<String>,
+) -> anyhow::Result<()> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => {
+            return Err(e).with_context(|| format!("Failed to read {}", path.to_string_lossy()))
+        }
+    };
+
+    std::fs::write(path, update(&contents)?)
+        .with_context(|| format!("Failed to write {}", path.to_string_lossy()))
+}
+
+/// Replaces the line that sets `key`, or appends one if there isn't any.
+fn set_env_var(contents: &str, key: &str, value: &str) -> String {
+    let line = format!("{key}=\"{value}\"");
+    let mut found = false;
+
+    let mut lines = contents
+        .lines()
+        .map(|l| match l.split_once('=') {
+            Some((k, _)) if k.trim() == key && !found => {
+                found = true;
+                line.clone()
+            }
+            _ => l.to_string(),
+        })
+        .collect::<Vec<_>>();
+    if !found {
+        lines.push(line);
+    }
+
+    lines.join("\n") + "\n"
+}
+
+/// Sets `chain.contract`, keeping the rest of the file as it was.
+fn set_config_contract(contents: &str, address: Address) -> anyhow::Result<String> {
+    let mut document = contents
+        .parse::<toml_edit::DocumentMut>()
+        .context("Invalid config file")?;
+    document
+        .entry("chain")
+        .or_insert(toml_edit::table())
+        .as_table_like_mut()
+        .context("`chain` in the config file isn't a table")?
+        .insert("contract", toml_edit::value(to_checksum(&address, None)));
+
+    Ok(document.to_string())
+}
+
+#[test]
+fn test_set_env_var() {
+    let env = "DUOPOW_CONTRACT=\"0x0\"\n# DUOPOW_MAX_GAS_PRICE=\"1.0\"\nDUOPOW_RPC=\"x\"\n";
+    assert_eq!(
+        set_env_var(env, "DUOPOW_CONTRACT", "0x1"),
+        "DUOPOW_CONTRACT=\"0x1\"\n# DUOPOW_MAX_GAS_PRICE=\"1.0\"\nDUOPOW_RPC=\"x\"\n"
+    );
+    assert_eq!(
+        set_env_var(env, "DUOPOW_MAX_GAS_PRICE", "2"),
+        format!("{env}DUOPOW_MAX_GAS_PRICE=\"2\"\n")
+    );
+    assert_eq!(
+        set_env_var("", "DUOPOW_CONTRACT", "0x1"),
+        "DUOPOW_CONTRACT=\"0x1\"\n"
+    );
+}
+
+#[test]
+fn test_set_config_contract() {
+    let address = "0x3652e47Cc0392825d5f2d7D8Fea7494ac4aC45ec"
+        .parse::<Address>()
+        .unwrap();
+
+    let config = "# the bot\n[chain]\nrpc = \"http://localhost:8545\" # anvil\n";
+    assert_eq!(
+        set_config_contract(config, address).unwrap(),
+        "# the bot\n[chain]\nrpc = \"http://localhost:8545\" # anvil\ncontract = \"0x3652e47Cc0392825d5f2d7D8Fea7494ac4aC45ec\"\n"
+    );
+
+    let config = "[features]\nsync_interval = 60\n";
+    let updated = set_config_contract(config, address).unwrap();
+    assert!(updated.starts_with(config));
+    assert_eq!(
+        updated.parse::<toml::Table>().unwrap()["chain"]["contract"].as_str(),
+        Some("0x3652e47Cc0392825d5f2d7D8Fea7494ac4aC45ec")
+    );
+}
@@ -0,0 +1,276 @@
+use async_trait::async_trait;
+use ethers::types::Address;
+use once_cell::sync::Lazy;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::metrics::time_duolingo;
+
+pub const DEFAULT_BASE_URL: &str = "https://www.duolingo.com/";
+
+const BROWSER_USER_AGENT: &str =
+    "Mozilla/5.0 (Macintosh; Intel Mac OS X 10.15; rv:127.0) Gecko/20100101 Firefox/127.0";
+
+pub static ETH_ADDRESS: Lazy<regex::Regex> =
+    Lazy::new(|| regex::Regex::new(r"0x[0-9a-fA-F]{40}").unwrap());
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserResponse {
+    pub streak: u32,
+    pub id: u64,
+    pub username: String,
+    pub bio: String,
+    pub name: String,
+    pub courses: Vec<CourseResponse>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CourseResponse {
+    pub title: String,
+    pub learning_language: String,
+    pub xp: u64,
+    pub from_language: String,
+    pub id: String,
+}
+
+/// The parts of a profile anyone can see, without a JWT.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PublicProfile {
+    pub username: String,
+    pub bio: String,
+    pub total_xp: u64,
+}
+
+impl PublicProfile {
+    pub fn address(&self) -> Option<Address> {
+        address_in_bio(&self.bio)
+    }
+}
+
+#[async_trait]
+pub trait DuolingoApi: Send + Sync {
+    async fn get_user_by_username(&self, username: &str) -> anyhow::Result<Option<UserResponse>>;
+
+    async fn get_user_total_xp(&self, uid: u64) -> anyhow::Result<u64>;
+
+    /// Returns `None` if there's no such user.
+    async fn get_public_profile(&self, uid: u64) -> anyhow::Result<Option<PublicProfile>>;
+
+    async fn get_user_by_uid(&self, uid: u64, jwt: &str) -> anyhow::Result<UserResponse>;
+
+    async fn update_bio(&self, uid: u64, jwt: &str, bio: &str) -> anyhow::Result<()>;
+}
+
+pub struct DuolingoClient {
+    http: reqwest::Client,
+    base_url: Url,
+    min_interval: Option<Duration>,
+    next_request: Mutex<Instant>,
+}
+
+impl DuolingoClient {
+    pub fn new(http: reqwest::Client, base_url: Url) -> Self {
+        Self {
+            http,
+            base_url,
+            min_interval: None,
+            next_request: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Spaces requests out so that at most `per_second` are made each second.
+    pub fn with_rate_limit(mut self, per_second: u32) -> Self {
+        self.min_interval = Some(Duration::from_secs(1) / per_second);
+        self
+    }
+
+    async fn throttle(&self) {
+        let Some(min_interval) = self.min_interval else {
+            return;
+        };
+
+        let at = {
+            let mut next_request = self.next_request.lock().await;
+            let at = (*next_request).max(Instant::now());
+            *next_request = at + min_interval;
+            at
+        };
+
+        tokio::time::sleep_until(at).await;
+    }
+
+    fn users_url(&self) -> String {
+        format!(
+            "{}/2017-06-30/users",
+            self.base_url.as_str().trim_end_matches('/')
+        )
+    }
+
+    fn user_url(&self, uid: u64) -> String {
+        format!("{}/{uid}", self.users_url())
+    }
+}
+
+#[async_trait]
+impl DuolingoApi for DuolingoClient {
+    async fn get_user_by_username(&self, username: &str) -> anyhow::Result<Option<UserResponse>> {
+        #[derive(Deserialize)]
+        struct UserRequestResponse {
+            users: Vec<UserResponse>,
+        }
+
+        self.throttle().await;
+
+        time_duolingo("get_user_by_username", async {
+            let mut response = self
+                .http
+                .get(self.users_url())
+                .query(&[("username", username)])
+                .send()
+                .await?
+                .error_for_status()?
+                .json::<UserRequestResponse>()
+                .await?;
+
+            Ok(response.users.pop())
+        })
+        .await
+    }
+
+    async fn get_user_total_xp(&self, uid: u64) -> anyhow::Result<u64> {
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct TotalXp {
+            total_xp: u64,
+        }
+
+        self.throttle().await;
+
+        time_duolingo("get_user_total_xp", async {
+            Ok(self
+                .http
+                .get(self.user_url(uid))
+                .query(&[("fields", "totalXp")])
+                .send()
+                .await?
+                .error_for_status()?
+                .json::<TotalXp>()
+                .await?
+                .total_xp)
+        })
+        .await
+    }
+
+    async fn get_public_profile(&self, uid: u64) -> anyhow::Result<Option<PublicProfile>> {
+        self.throttle().await;
+
+        time_duolingo("get_public_profile", async {
+            let response = self
+                .http
+                .get(self.user_url(uid))
+                .query(&[("fields", "username,bio,totalXp")])
+                .send()
+                .await?;
+            if response.status() == reqwest::StatusCode::NOT_FOUND {
+                return Ok(None);
+            }
+
+            Ok(Some(response.error_for_status()?.json().await?))
+        })
+        .await
+    }
+
+    async fn get_user_by_uid(&self, uid: u64, jwt: &str) -> anyhow::Result<UserResponse> {
+        self.throttle().await;
+
+        time_duolingo("get_user_by_uid", async {
+            let response = self
+                .http
+                .get(self.user_url(uid))
+                .header("User-Agent", BROWSER_USER_AGENT)
+                .bearer_auth(jwt)
+                .send()
+                .await?
+                .error_for_status()?;
+
+            let user_response = response.json::<UserResponse>().await?;
+
+            Ok(user_response)
+        })
+        .await
+    }
+
+    async fn update_bio(&self, uid: u64, jwt: &str, bio: &str) -> anyhow::Result<()> {
+        self.throttle().await;
+
+        time_duolingo("update_bio", async {
+            self.http
+                .patch(self.user_url(uid))
+                .query(&[("fields", "bio")])
+                .bearer_auth(jwt)
+                .header("User-Agent", BROWSER_USER_AGENT)
+                .header(
+                    "Referer",
+                    format!(
+                        "{}/settings/profile",
+                        self.base_url.as_str().trim_end_matches('/')
+                    ),
+                )
+                .json(&json!({
+                    "bio": bio,
+                }))
+                .send()
+                .await?
+                .error_for_status()?;
+
+            Ok(())
+        })
+        .await
+    }
+}
+
+/// Returns `None` if there's no such user.
+pub async fn get_user_uid_and_maybe_address(
+    duolingo: &dyn DuolingoApi,
+    username: &str,
+) -> anyhow::Result<Option<(u64, Option<Address>)>> {
+    let Some(response) = duolingo.get_user_by_username(username).await? else {
+        return Ok(None);
+    };
+
+    Ok(Some((response.id, address_in_bio(&response.bio))))
+}
+
+/// The first address in `bio`, if there is one.
+pub fn address_in_bio(bio: &str) -> Option<Address> {
+    ETH_ADDRESS
+        .find(bio)
+        .and_then(|address_match| address_match.as_str().parse().ok())
+}
+
+/// `jwt` must already have been checked to belong to `uid`.
+pub async fn add_address_to_profile(
+    duolingo: &dyn DuolingoApi,
+    uid: u64,
+    jwt: &str,
+    address: Address,
+) -> anyhow::Result<()> {
+    let original_bio = duolingo.get_user_by_uid(uid, jwt).await?.bio;
+    let address_str = ethers::utils::to_checksum(&address, None);
+    let new_bio = if ETH_ADDRESS.is_match(&original_bio) {
+        ETH_ADDRESS.replace(&original_bio, address_str)
+    } else {
+        std::borrow::Cow::Owned(format!("{} {}", original_bio, address_str))
+    };
+
+    duolingo.update_bio(uid, jwt, &new_bio).await?;
+
+    Ok(())
+}
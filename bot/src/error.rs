@@ -0,0 +1,144 @@
+use ethers::{
+    contract::ContractError,
+    providers::{Middleware, ProviderError},
+    types::{TxHash, U256},
+};
+use reqwest::{StatusCode, Url};
+use teloxide::utils::html;
+
+use crate::{tx::describe_revert, OwnerMiddleware};
+
+/// Everything a handler can fail with, grouped by what the user should be
+/// told about it.
+#[derive(Debug, thiserror::Error)]
+pub enum BotError {
+    #[error("Duolingo user {0:?} not found")]
+    UserNotFound(String),
+    #[error("Rate limited by Duolingo")]
+    RateLimited,
+    #[error("Duolingo request failed: {0}")]
+    Duolingo(#[source] reqwest::Error),
+    #[error("RPC request failed: {0:#}")]
+    Rpc(#[source] anyhow::Error),
+    #[error("Transaction reverted: {reason:?}")]
+    Reverted {
+        reason: Option<String>,
+        tx_hash: Option<TxHash>,
+    },
+    #[error("Gas price of {price} wei is above the limit of {max} wei")]
+    GasPriceTooHigh { price: U256, max: U256 },
+    #[error("Owner balance of {balance} wei is below the minimum of {min} wei")]
+    LowBalance { balance: U256, min: U256 },
+    #[error("{0}")]
+    Validation(String),
+    #[error(transparent)]
+    Telegram(#[from] teloxide::RequestError),
+    #[error(transparent)]
+    Other(anyhow::Error),
+}
+
+impl BotError {
+    /// An HTML reply explaining the error to the user.
+    pub fn user_message(&self, explorer_url: &Url) -> String {
+        match self {
+            BotError::UserNotFound(username) => format!(
+                "I couldn't find the Duolingo user <code>{}</code>.",
+                html::escape(username)
+            ),
+            BotError::RateLimited => {
+                "Duolingo is asking us to slow down. Please try again in a few minutes.".to_string()
+            }
+            BotError::Duolingo(_) => {
+                "Duolingo didn't answer properly. Please try again later.".to_string()
+            }
+            BotError::Rpc(_) => {
+                "I couldn't reach the blockchain. Please try again later.".to_string()
+            }
+            BotError::Reverted { reason, tx_hash } => {
+                describe_revert(explorer_url, reason.as_deref(), *tx_hash)
+            }
+            BotError::GasPriceTooHigh { .. } => {
+                "Gas is unusually expensive right now, so I'm holding off on transactions. \
+                 Please try again later."
+                    .to_string()
+            }
+            BotError::LowBalance { .. } => {
+                "The bot's wallet is running low on funds, so I'm holding off on transactions \
+                 until it's topped up. Please try again later."
+                    .to_string()
+            }
+            BotError::Validation(message) => html::escape(message),
+            BotError::Telegram(_) | BotError::Other(_) => {
+                "Something went wrong on our end. Please try again later.".to_string()
+            }
+        }
+    }
+
+    /// Whether the error is the user's or a third party's doing, rather than
+    /// something to look into. Duolingo being unreachable or failing isn't.
+    pub fn is_expected(&self) -> bool {
+        matches!(
+            self,
+            BotError::UserNotFound(_)
+                | BotError::RateLimited
+                | BotError::Reverted { .. }
+                | BotError::GasPriceTooHigh { .. }
+                | BotError::LowBalance { .. }
+                | BotError::Validation(_)
+        ) || matches!(self, BotError::Duolingo(e) if e.status() == Some(StatusCode::NOT_FOUND))
+    }
+}
+
+impl From<anyhow::Error> for BotError {
+    fn from(e: anyhow::Error) -> Self {
+        let e = match e.downcast::<BotError>() {
+            Ok(e) => return e,
+            Err(e) => e,
+        };
+
+        let e = match e.downcast::<reqwest::Error>() {
+            Ok(e) if e.status() == Some(StatusCode::TOO_MANY_REQUESTS) => {
+                return BotError::RateLimited
+            }
+            Ok(e) => return BotError::Duolingo(e),
+            Err(e) => e,
+        };
+
+        let e = match e.downcast::<teloxide::RequestError>() {
+            Ok(e) => return BotError::Telegram(e),
+            Err(e) => e,
+        };
+
+        if e.is::<ProviderError>() || e.is::<ContractError<OwnerMiddleware>>() {
+            BotError::Rpc(e)
+        } else {
+            BotError::Other(e)
+        }
+    }
+}
+
+impl<M: Middleware + 'static> From<ContractError<M>> for BotError {
+    fn from(e: ContractError<M>) -> Self {
+        BotError::Rpc(e.into())
+    }
+}
+
+#[tokio::test]
+async fn test_only_missing_duolingo_users_are_expected() {
+    let url = crate::mock_duolingo::MockDuolingo::default()
+        .spawn()
+        .unwrap();
+    let e = reqwest::get(url.join("2017-06-30/users/1001").unwrap())
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap_err();
+    let e = BotError::from(anyhow::Error::from(e));
+    assert!(matches!(e, BotError::Duolingo(_)));
+    assert!(e.is_expected());
+
+    let e = reqwest::get("http://127.0.0.1:1/").await.unwrap_err();
+    let e = BotError::from(anyhow::Error::from(e));
+    assert!(matches!(e, BotError::Duolingo(_)));
+    assert!(!e.is_expected());
+}
@@ -0,0 +1,201 @@
+use std::{
+    net::TcpListener,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    extract::{Path, State},
+    http::{header, HeaderMap},
+    routing::post,
+    Json, Router,
+};
+use serde_json::json;
+
+pub const BOT_ID: u64 = 4242;
+pub const BOT_USERNAME: &str = "DuoPowBot";
+
+#[derive(Debug, Clone)]
+pub struct SentMessage {
+    pub chat_id: i64,
+    pub message_id: i32,
+    pub text: String,
+    pub deleted: bool,
+}
+
+#[derive(Default)]
+struct Inner {
+    messages: Vec<SentMessage>,
+    next_message_id: i32,
+    webhook: Option<(String, Option<String>)>,
+}
+
+/// Records what the bot sends to the Telegram Bot API so tests can assert on
+/// it. Only the methods the bot actually calls are implemented.
+#[derive(Clone, Default)]
+pub struct FakeTelegram {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl FakeTelegram {
+    pub fn spawn(&self) -> anyhow::Result<reqwest::Url> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        listener.set_nonblocking(true)?;
+        let url = reqwest::Url::parse(&format!("http://{}/", listener.local_addr()?))?;
+        let server = axum::Server::from_tcp(listener)?.serve(
+            Router::new()
+                .route("/:token/:method", post(call))
+                .with_state(self.clone())
+                .into_make_service(),
+        );
+        tokio::spawn(async move {
+            if let Err(e) = server.await {
+                log::error!("Fake Telegram server stopped: {e}");
+            }
+        });
+        Ok(url)
+    }
+
+    pub fn messages(&self) -> Vec<SentMessage> {
+        self.inner.lock().unwrap().messages.clone()
+    }
+
+    /// Messages in `chat_id` that have not been deleted, oldest first.
+    pub fn visible(&self, chat_id: i64) -> Vec<String> {
+        self.messages()
+            .into_iter()
+            .filter(|m| m.chat_id == chat_id && !m.deleted)
+            .map(|m| m.text)
+            .collect()
+    }
+
+    /// The URL and secret token of the webhook, if one is set.
+    pub fn webhook(&self) -> Option<(String, Option<String>)> {
+        self.inner.lock().unwrap().webhook.clone()
+    }
+
+    pub fn me() -> teloxide::types::Me {
+        serde_json::from_value(json!({
+            "id": BOT_ID,
+            "is_bot": true,
+            "first_name": "DuoPow",
+            "username": BOT_USERNAME,
+            "can_join_groups": false,
+            "can_read_all_group_messages": false,
+            "supports_inline_queries": false,
+        }))
+        .unwrap()
+    }
+}
+
+fn message_json(chat_id: i64, message_id: i32, text: &str) -> serde_json::Value {
+    json!({
+        "message_id": message_id,
+        "date": 0,
+        "chat": { "id": chat_id, "type": "private", "first_name": "Test" },
+        "from": {
+            "id": BOT_ID,
+            "is_bot": true,
+            "first_name": "DuoPow",
+            "username": BOT_USERNAME,
+        },
+        "text": text,
+    })
+}
+
+/// The text fields of a `multipart/form-data` body, which is how requests
+/// that can carry files (like `setWebhook`) are sent.
+fn form_fields(boundary: &str, body: &str) -> serde_json::Value {
+    let fields = body
+        .split(&format!("--{boundary}"))
+        .filter_map(|part| {
+            let (headers, value) = part.split_once("\r\n\r\n")?;
+            let name = headers.split("name=\"").nth(1)?.split('"').next()?;
+            Some((name.to_owned(), json!(value.trim_end_matches("\r\n"))))
+        })
+        .collect();
+
+    serde_json::Value::Object(fields)
+}
+
+async fn call(
+    State(telegram): State<FakeTelegram>,
+    Path((_token, method)): Path<(String, String)>,
+    headers: HeaderMap,
+    body: String,
+) -> Json<serde_json::Value> {
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    let body = match content_type.split_once("boundary=") {
+        Some((_, boundary)) => form_fields(boundary, &body),
+        None => serde_json::from_str(&body).unwrap_or_default(),
+    };
+    let chat_id = body["chat_id"].as_i64().unwrap_or_default();
+    let mut inner = telegram.inner.lock().unwrap();
+
+    let result = match method.to_ascii_lowercase().as_str() {
+        "getme" => serde_json::to_value(FakeTelegram::me()).unwrap(),
+        "setwebhook" => {
+            inner.webhook = Some((
+                body["url"].as_str().unwrap_or_default().to_owned(),
+                body["secret_token"].as_str().map(str::to_owned),
+            ));
+            json!(true)
+        }
+        "deletewebhook" => {
+            inner.webhook = None;
+            json!(true)
+        }
+        "sendmessage" => {
+            inner.next_message_id += 1;
+            let message = SentMessage {
+                chat_id,
+                message_id: inner.next_message_id,
+                text: body["text"].as_str().unwrap_or_default().to_owned(),
+                deleted: false,
+            };
+            let result = message_json(chat_id, message.message_id, &message.text);
+            inner.messages.push(message);
+            result
+        }
+        "editmessagetext" => {
+            let message_id = body["message_id"].as_i64().unwrap_or_default() as i32;
+            let Some(message) = inner
+                .messages
+                .iter_mut()
+                .find(|m| m.chat_id == chat_id && m.message_id == message_id && !m.deleted)
+            else {
+                return Json(json!({
+                    "ok": false,
+                    "error_code": 400,
+                    "description": "Bad Request: message to edit not found",
+                }));
+            };
+            message.text = body["text"].as_str().unwrap_or_default().to_owned();
+            message_json(chat_id, message_id, &message.text)
+        }
+        "deletemessage" => {
+            // deleting a message sent by the user is also allowed, but those
+            // aren't tracked here
+            let message_id = body["message_id"].as_i64().unwrap_or_default() as i32;
+            if let Some(message) = inner
+                .messages
+                .iter_mut()
+                .find(|m| m.chat_id == chat_id && m.message_id == message_id)
+            {
+                message.deleted = true;
+            }
+            json!(true)
+        }
+        _ => {
+            return Json(json!({
+                "ok": false,
+                "error_code": 404,
+                "description": format!("Not Found: {method} is not implemented by the fake"),
+            }))
+        }
+    };
+
+    Json(json!({ "ok": true, "result": result }))
+}
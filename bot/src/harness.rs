@@ -0,0 +1,1063 @@
+use std::{
+    ops::ControlFlow,
+    sync::{
+        atomic::{AtomicI32, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use dptree::deps;
+use ethers::{
+    middleware::SignerMiddleware,
+    providers::{Http, Middleware, Provider},
+    signers::{LocalWallet, Signer},
+    types::{Address, TransactionRequest, U256},
+    utils::{Anvil, AnvilInstance},
+};
+use serde_json::json;
+use teloxide::{
+    dispatching::{
+        dialogue::{InMemStorage, Storage},
+        DefaultKey, UpdateHandler,
+    },
+    prelude::*,
+};
+
+use crate::{
+    admin::{self, TxResult},
+    duolingo::DuolingoClient,
+    error::BotError,
+    fake_telegram::FakeTelegram,
+    handler, indexer, metrics,
+    mock_duolingo::{jwt_for, MockDuolingo, MockUser},
+    reconcile,
+    registry::Registry,
+    storage::{open_dialogue_storage, DialogueStorage, StorageKind},
+    sync,
+    tx::TxOutcome,
+    tx_queue::TxQueue,
+    webhook, ChatState, Connections, DuolingoPowContract, OwnerContract,
+};
+
+/// Drives `handler()` with fake Telegram updates against the mock Duolingo
+/// server and, optionally, a `DuolingoPow` deployment on a local anvil node.
+pub struct Harness {
+    pub duolingo: MockDuolingo,
+    pub telegram: FakeTelegram,
+    pub contract: OwnerContract,
+    bot: Bot,
+    handler: UpdateHandler<BotError>,
+    connections: Arc<Connections>,
+    storage: Arc<DialogueStorage>,
+    next_id: AtomicI32,
+    _anvil: Option<AnvilInstance>,
+}
+
+impl Harness {
+    /// A harness whose contract points at an unreachable RPC, for flows that
+    /// never touch the chain.
+    pub async fn offline(users: impl IntoIterator<Item = MockUser>) -> Self {
+        let provider = Provider::<Http>::try_from("http://127.0.0.1:1").unwrap();
+        let wallet = LocalWallet::new(&mut ethers::core::rand::thread_rng());
+        let contract = DuolingoPowContract::new(
+            Address::zero(),
+            Arc::new(SignerMiddleware::new(provider, wallet)),
+        );
+
+        Self::build(users, contract, None).await
+    }
+
+    /// A harness with a fresh `DuolingoPow` deployed to a local anvil node.
+    /// Requires `anvil` on the `PATH`.
+    pub async fn with_anvil(users: impl IntoIterator<Item = MockUser>) -> Self {
+        let anvil = Anvil::new().spawn();
+        let provider = Provider::<Http>::try_from(anvil.endpoint()).unwrap();
+        let wallet = LocalWallet::from(anvil.keys()[0].clone()).with_chain_id(anvil.chain_id());
+        let client = Arc::new(SignerMiddleware::new(provider, wallet));
+
+        let contract = DuolingoPowContract::deploy(
+            client,
+            ("Proof of Duolingo".to_string(), "POD".to_string()),
+        )
+        .unwrap()
+        .send()
+        .await
+        .unwrap();
+
+        Self::build(users, contract, Some(anvil)).await
+    }
+
+    async fn build(
+        users: impl IntoIterator<Item = MockUser>,
+        contract: OwnerContract,
+        anvil: Option<AnvilInstance>,
+    ) -> Self {
+        let duolingo = MockDuolingo::new(users);
+        let telegram = FakeTelegram::default();
+
+        let bot = Bot::new("TEST_TOKEN").set_api_url(telegram.spawn().unwrap());
+        let duolingo_client =
+            DuolingoClient::new(reqwest::Client::new(), duolingo.clone().spawn().unwrap());
+
+        let connections = Arc::new(Connections {
+            duolingo: Box::new(duolingo_client),
+            contract_address: contract.address(),
+            contract: contract.clone(),
+            registry: Registry::in_memory().await,
+            tx_queue: TxQueue::spawn(contract.clone(), 1, 0, None, None, None),
+            explorer_url: "https://explorer.invalid/".parse().unwrap(),
+            admins: vec![UserId(ADMIN_CHAT as u64)],
+        });
+
+        Self {
+            duolingo,
+            telegram,
+            contract,
+            bot,
+            handler: handler(),
+            connections,
+            storage: InMemStorage::<ChatState>::new().erase(),
+            next_id: AtomicI32::new(0),
+            _anvil: anvil,
+        }
+    }
+
+    pub fn with_storage(mut self, storage: Arc<DialogueStorage>) -> Self {
+        self.storage = storage;
+        self
+    }
+
+    pub fn with_xp_batch_window(mut self, window: Duration) -> Self {
+        Arc::get_mut(&mut self.connections).unwrap().tx_queue =
+            TxQueue::spawn(self.contract.clone(), 1, 0, None, None, Some(window));
+        self
+    }
+
+    pub fn with_min_balance(mut self, min_balance: U256) -> Self {
+        Arc::get_mut(&mut self.connections).unwrap().tx_queue =
+            TxQueue::spawn(self.contract.clone(), 1, 0, None, Some(min_balance), None);
+        self
+    }
+
+    /// Sends `text` from the private chat `chat_id`, runs the handler to
+    /// completion, and returns every message the bot sent in response.
+    pub async fn send(&self, chat_id: i64, text: &str) -> anyhow::Result<Vec<String>> {
+        // `Update` only deserializes from borrowed keys, so go through a string
+        let update: Update = serde_json::from_str(&self.update(chat_id, text).to_string())?;
+
+        let sent_before = self.telegram.messages().len();
+
+        let result = self
+            .handler
+            .dispatch(deps![
+                self.bot.clone(),
+                FakeTelegram::me(),
+                update,
+                self.connections.clone(),
+                self.storage.clone()
+            ])
+            .await;
+
+        if let ControlFlow::Break(Err(e)) = result {
+            return Err(e.into());
+        }
+
+        Ok(self
+            .telegram
+            .messages()
+            .into_iter()
+            .skip(sent_before)
+            .filter(|m| m.chat_id == chat_id)
+            .map(|m| m.text)
+            .collect())
+    }
+
+    /// The update Telegram would send for `text` in the private chat
+    /// `chat_id`.
+    pub fn update(&self, chat_id: i64, text: &str) -> serde_json::Value {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst) + 1;
+
+        json!({
+            "update_id": id,
+            "message": {
+                "message_id": 100_000 + id,
+                "date": 0,
+                "chat": { "id": chat_id, "type": "private", "first_name": "Test" },
+                "from": { "id": chat_id, "is_bot": false, "first_name": "Test" },
+                "text": text,
+            },
+        })
+    }
+
+    /// A dispatcher like the one `run` uses, for driving the bot through an
+    /// update listener instead of [`Harness::send`].
+    pub fn dispatcher(&self) -> Dispatcher<Bot, BotError, DefaultKey> {
+        crate::dispatcher(
+            self.bot.clone(),
+            self.connections.clone(),
+            self.storage.clone(),
+        )
+    }
+
+    /// Runs one pass of the XP sync, returning how many UIDs were minted.
+    pub async fn sync(&self, notify: bool) -> anyhow::Result<usize> {
+        sync::sync_once(&self.bot, &self.connections, notify).await
+    }
+
+    pub fn registry(&self) -> &Registry {
+        &self.connections.registry
+    }
+
+    pub async fn user_in_contract(&self, uid: u64) -> (Address, U256) {
+        self.contract.users(uid.into()).await.unwrap()
+    }
+}
+
+const ADMIN_CHAT: i64 = 7000;
+const ALICE_CHAT: i64 = 7001;
+const ALICE_UID: u64 = 1001;
+const ALICE_ADDRESS: &str = "0x69AA0361Dbb0527d4F1e5312403Bd41788fe61Fe";
+
+fn alice() -> MockUser {
+    MockUser::new(ALICE_UID, "alice")
+        .with_bio("hola")
+        .with_total_xp(100)
+}
+
+fn contains(replies: &[String], needle: &str) -> bool {
+    replies.iter().any(|r| r.contains(needle))
+}
+
+async fn link(harness: &Harness, chat_id: i64, username: &str, uid: u64, address: &str) {
+    harness.send(chat_id, "/link").await.unwrap();
+    harness.send(chat_id, username).await.unwrap();
+    harness.send(chat_id, address).await.unwrap();
+    let replies = harness.send(chat_id, &jwt_for(uid)).await.unwrap();
+    assert!(contains(&replies, "Profile linked!"));
+}
+
+async fn link_alice(harness: &Harness) {
+    link(harness, ALICE_CHAT, "alice", ALICE_UID, ALICE_ADDRESS).await;
+}
+
+#[tokio::test]
+async fn test_help() {
+    let harness = Harness::offline([]).await;
+
+    let replies = harness.send(ALICE_CHAT, "/help").await.unwrap();
+
+    assert!(contains(&replies, "These commands are supported:"));
+    assert!(contains(&replies, "This bot talks to the contract"));
+    assert!(!contains(&replies, "/reconcile"));
+}
+
+#[tokio::test]
+async fn test_webhook() {
+    let harness = Harness::offline([]).await;
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url: reqwest::Url = format!("http://{}/telegram", listener.local_addr().unwrap())
+        .parse()
+        .unwrap();
+    let updates = webhook::listen(
+        harness.bot.clone(),
+        url.clone(),
+        listener,
+        Some("hunter2".to_string()),
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        harness.telegram.webhook(),
+        Some((url.to_string(), Some("hunter2".to_string())))
+    );
+
+    let mut dispatcher = harness.dispatcher();
+    tokio::spawn(async move {
+        dispatcher
+            .dispatch_with_listener(updates, LoggingErrorHandler::new())
+            .await
+    });
+
+    let post = |secret: &str| {
+        reqwest::Client::new()
+            .post(url.clone())
+            .header("X-Telegram-Bot-Api-Secret-Token", secret)
+            .json(&harness.update(ALICE_CHAT, "/help"))
+            .send()
+    };
+
+    let response = post("hunter3").await.unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::UNAUTHORIZED);
+
+    let response = post("hunter2").await.unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while harness.telegram.visible(ALICE_CHAT).is_empty() {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await
+    .unwrap();
+    let replies = harness.telegram.visible(ALICE_CHAT);
+    assert_eq!(replies.len(), 1);
+    assert!(contains(&replies, "These commands are supported:"));
+}
+
+#[tokio::test]
+async fn test_metrics_and_health() {
+    let harness = Harness::offline([alice()]).await;
+    harness.send(ALICE_CHAT, "/help").await.unwrap();
+    harness.send(ALICE_CHAT, "/check alice").await.unwrap();
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    metrics::serve(listener, harness.connections.clone()).unwrap();
+
+    let response = reqwest::get(format!("{url}/metrics")).await.unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    let body = response.text().await.unwrap();
+    assert!(body.contains(r#"duopow_commands_total{command="help"}"#));
+    assert!(body.contains(r#"duopow_commands_total{command="check"}"#));
+    assert!(body.contains(
+        r#"duopow_duolingo_request_duration_seconds_count{endpoint="get_user_by_username"}"#
+    ));
+
+    // the offline harness has no chain to talk to
+    let response = reqwest::get(format!("{url}/healthz")).await.unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::SERVICE_UNAVAILABLE);
+}
+
+#[tokio::test]
+async fn test_link_dialogue() {
+    let harness = Harness::offline([alice()]).await;
+
+    let replies = harness.send(ALICE_CHAT, "/link").await.unwrap();
+    assert!(contains(&replies, "what's your username?"));
+
+    let replies = harness.send(ALICE_CHAT, "nobody").await.unwrap();
+    assert!(contains(&replies, "User not found. Please try again."));
+
+    let replies = harness.send(ALICE_CHAT, "alice").await.unwrap();
+    assert!(contains(&replies, "What is your Taiko address?"));
+
+    let replies = harness.send(ALICE_CHAT, "0x1234").await.unwrap();
+    assert!(contains(&replies, "Invalid address. Please try again."));
+
+    let replies = harness.send(ALICE_CHAT, ALICE_ADDRESS).await.unwrap();
+    assert!(contains(&replies, "please send your JWT"));
+
+    let replies = harness.send(ALICE_CHAT, "not.a.token").await.unwrap();
+    assert!(contains(&replies, "That JWT couldn't be decoded."));
+
+    let replies = harness.send(ALICE_CHAT, &jwt_for(1002)).await.unwrap();
+    assert!(contains(
+        &replies,
+        "That JWT belongs to a different Duolingo account."
+    ));
+
+    let replies = harness.send(ALICE_CHAT, &jwt_for(ALICE_UID)).await.unwrap();
+    assert!(contains(&replies, "Profile linked!"));
+    assert_eq!(
+        harness.duolingo.user(ALICE_UID).unwrap().bio,
+        format!("hola {ALICE_ADDRESS}")
+    );
+
+    // back at the start of the dialogue, so commands work again
+    let replies = harness.send(ALICE_CHAT, "/help").await.unwrap();
+    assert!(contains(&replies, "These commands are supported:"));
+}
+
+#[tokio::test]
+async fn test_link_with_bio_challenge() {
+    let harness = Harness::offline([alice()]).await;
+
+    harness.send(ALICE_CHAT, "/link").await.unwrap();
+    harness.send(ALICE_CHAT, "alice").await.unwrap();
+    let replies = harness.send(ALICE_CHAT, ALICE_ADDRESS).await.unwrap();
+    let expected = replies
+        .iter()
+        .find_map(|r| r.split_once("<code>")?.1.split_once("</code>"))
+        .unwrap()
+        .0
+        .to_owned();
+    let (address, code) = expected.split_once(' ').unwrap();
+    assert_eq!(address, ALICE_ADDRESS);
+    assert!(code.starts_with("duopow-"));
+
+    let replies = harness.send(ALICE_CHAT, "done").await.unwrap();
+    assert!(contains(&replies, "I can't see"));
+
+    // the code alone doesn't say which address to trust
+    harness.duolingo.set_bio(ALICE_UID, &format!("hola {code}"));
+    let replies = harness.send(ALICE_CHAT, "done").await.unwrap();
+    assert!(contains(&replies, "I can't see"));
+    assert_eq!(harness.registry().owner(ALICE_UID).await.unwrap(), None);
+
+    harness
+        .duolingo
+        .set_bio(ALICE_UID, &format!("hola {ALICE_ADDRESS} {code}"));
+    let replies = harness.send(ALICE_CHAT, "done").await.unwrap();
+    assert!(contains(&replies, "Profile linked!"));
+    assert_eq!(
+        harness.registry().owner(ALICE_UID).await.unwrap(),
+        Some(UserId(ALICE_CHAT as u64))
+    );
+
+    // no JWT changed hands, so the bot can't have touched the bio
+    assert_eq!(
+        harness.duolingo.user(ALICE_UID).unwrap().bio,
+        format!("hola {ALICE_ADDRESS} {code}")
+    );
+}
+
+#[tokio::test]
+async fn test_link_with_wallet_signature() {
+    let harness = Harness::offline([alice()]).await;
+    let wallet = LocalWallet::new(&mut ethers::core::rand::thread_rng());
+    let address = ethers::utils::to_checksum(&wallet.address(), None);
+
+    harness.send(ALICE_CHAT, "/linkwallet").await.unwrap();
+    harness.send(ALICE_CHAT, "alice").await.unwrap();
+    let replies = harness.send(ALICE_CHAT, &address).await.unwrap();
+    let message = replies
+        .iter()
+        .find_map(|r| r.split_once("<pre>")?.1.split_once("</pre>"))
+        .unwrap()
+        .0;
+    assert!(message.contains("Nonce: "));
+
+    let impostor = LocalWallet::new(&mut ethers::core::rand::thread_rng());
+    let signature = impostor.sign_message(message).await.unwrap();
+    let replies = harness
+        .send(ALICE_CHAT, &signature.to_string())
+        .await
+        .unwrap();
+    assert!(contains(&replies, "doesn't match your address"));
+
+    let signature = wallet.sign_message(message).await.unwrap();
+    let replies = harness
+        .send(ALICE_CHAT, &format!("0x{signature}"))
+        .await
+        .unwrap();
+    assert!(contains(&replies, "Signature verified!"));
+    let code = replies
+        .iter()
+        .find_map(|r| r.split_once("<code>")?.1.split_once("</code>"))
+        .unwrap()
+        .0
+        .to_owned();
+
+    let replies = harness.send(ALICE_CHAT, "done").await.unwrap();
+    assert!(contains(&replies, "I can't see"));
+    assert_eq!(harness.registry().owner(ALICE_UID).await.unwrap(), None);
+
+    harness.duolingo.set_bio(ALICE_UID, &format!("hola {code}"));
+    let replies = harness.send(ALICE_CHAT, "done").await.unwrap();
+    assert!(contains(&replies, "Profile linked!"));
+    assert_eq!(
+        harness.registry().owner(ALICE_UID).await.unwrap(),
+        Some(UserId(ALICE_CHAT as u64))
+    );
+    assert_eq!(
+        harness
+            .registry()
+            .account(ALICE_UID)
+            .await
+            .unwrap()
+            .unwrap()
+            .verified_address,
+        Some(wallet.address())
+    );
+}
+
+#[tokio::test]
+async fn test_link_survives_restart() {
+    let db = std::env::temp_dir().join(format!("duopow-test-{}.sqlite", std::process::id()));
+    let _ = std::fs::remove_file(&db);
+    let jwt = jwt_for(ALICE_UID);
+
+    {
+        let harness = Harness::offline([alice()]).await.with_storage(
+            open_dialogue_storage(StorageKind::Sqlite, &db)
+                .await
+                .unwrap(),
+        );
+        harness.send(ALICE_CHAT, "/link").await.unwrap();
+        harness.send(ALICE_CHAT, "alice").await.unwrap();
+    }
+
+    let harness = Harness::offline([alice()]).await.with_storage(
+        open_dialogue_storage(StorageKind::Sqlite, &db)
+            .await
+            .unwrap(),
+    );
+    let replies = harness.send(ALICE_CHAT, ALICE_ADDRESS).await.unwrap();
+    assert!(contains(&replies, "please send your JWT"));
+    let replies = harness.send(ALICE_CHAT, &jwt).await.unwrap();
+    assert!(contains(&replies, "Profile linked!"));
+
+    let contents = String::from_utf8_lossy(&std::fs::read(&db).unwrap()).into_owned();
+    assert!(!contents.contains(&jwt));
+
+    let _ = std::fs::remove_file(&db);
+}
+
+#[tokio::test]
+async fn test_only_linked_owner_can_register_and_unregister() {
+    const MALLORY_CHAT: i64 = 6666;
+
+    let harness = Harness::offline([alice().with_bio(&format!("hola {ALICE_ADDRESS}"))]).await;
+
+    let replies = harness.send(ALICE_CHAT, "/unregister alice").await.unwrap();
+    assert!(contains(&replies, "Use /link to prove that it's yours."));
+
+    link_alice(&harness).await;
+
+    let replies = harness
+        .send(MALLORY_CHAT, "/unregister alice")
+        .await
+        .unwrap();
+    assert!(contains(&replies, "Use /link to prove that it's yours."));
+    let replies = harness.send(MALLORY_CHAT, "/register alice").await.unwrap();
+    assert!(contains(&replies, "Use /link to prove that it's yours."));
+}
+
+#[tokio::test]
+async fn test_reconcile_is_for_admins() {
+    let harness = Harness::offline([]).await;
+
+    let replies = harness.send(ALICE_CHAT, "/reconcile").await.unwrap();
+    assert!(contains(&replies, "Only the bot's admins can do that."));
+
+    let replies = harness.send(ADMIN_CHAT, "/reconcile fix").await.unwrap();
+    assert!(contains(&replies, "Nothing has been indexed yet"));
+}
+
+#[tokio::test]
+async fn test_username_required_until_linked() {
+    let harness = Harness::offline([alice()]).await;
+
+    for command in ["/check", "/update", "/register", "/unregister"] {
+        let replies = harness.send(ALICE_CHAT, command).await.unwrap();
+        assert!(contains(&replies, "Please give a Duolingo username"));
+    }
+}
+
+#[tokio::test]
+async fn test_linked_account_survives_a_rename() {
+    let harness = Harness::offline([alice()]).await;
+    link_alice(&harness).await;
+    harness.duolingo.set_username(ALICE_UID, "alicia");
+
+    // the lookup goes by UID, so each gets past it and fails at the chain,
+    // which the offline harness doesn't have
+    for command in ["/check", "/update", "/register", "/unregister"] {
+        let sent_before = harness.telegram.messages().len();
+        assert!(harness.send(ALICE_CHAT, command).await.is_err());
+        let sent: Vec<_> = harness.telegram.messages()[sent_before..].to_vec();
+        assert!(
+            !sent.iter().any(|m| m.text.contains("couldn't find")
+                || m.text.contains("Only the Telegram account")),
+            "{command}: {sent:?}"
+        );
+    }
+
+    let replies = harness.send(ALICE_CHAT, "/check alice").await.unwrap();
+    assert!(contains(
+        &replies,
+        "I couldn't find the Duolingo user <code>alice</code>."
+    ));
+}
+
+#[tokio::test]
+async fn test_errors_are_reported_in_the_chat() {
+    let harness = Harness::offline([alice()]).await;
+
+    let replies = harness.send(ALICE_CHAT, "/update nobody").await.unwrap();
+    assert!(contains(
+        &replies,
+        "I couldn't find the Duolingo user <code>nobody</code>."
+    ));
+
+    // the offline harness has no chain to talk to
+    link_alice(&harness).await;
+    let sent_before = harness.telegram.messages().len();
+    let result = harness.send(ALICE_CHAT, "/update").await;
+    assert!(result.is_err());
+
+    // the status message itself turns into the error
+    let sent: Vec<_> = harness.telegram.messages()[sent_before..].to_vec();
+    assert_eq!(sent.len(), 1);
+    assert!(!sent[0].deleted);
+    assert!(sent[0].text.contains("I couldn't reach the blockchain."));
+
+    assert!(!harness
+        .telegram
+        .visible(ALICE_CHAT)
+        .iter()
+        .any(|m| m.contains("loading your Duolingo profile") || m.contains("Minting")));
+}
+
+#[tokio::test]
+#[ignore = "requires anvil"]
+async fn test_register_update_check_unregister() {
+    let harness = Harness::with_anvil([alice().with_bio(&format!("hola {ALICE_ADDRESS}"))]).await;
+    let address: Address = ALICE_ADDRESS.parse().unwrap();
+    link_alice(&harness).await;
+
+    let replies = harness.send(ALICE_CHAT, "/register alice").await.unwrap();
+    assert!(contains(&replies, "Registered!"));
+    assert_eq!(
+        harness.user_in_contract(ALICE_UID).await,
+        (address, 100.into())
+    );
+
+    let replies = harness.send(ALICE_CHAT, "/register").await.unwrap();
+    assert!(contains(&replies, "Already registered!"));
+
+    // the contract's revert reason is explained rather than swallowed
+    const BOB_CHAT: i64 = 7002;
+    harness
+        .duolingo
+        .insert_user(MockUser::new(1002, "bob").with_total_xp(5));
+    link(&harness, BOB_CHAT, "bob", 1002, ALICE_ADDRESS).await;
+    let replies = harness.send(BOB_CHAT, "/register").await.unwrap();
+    assert!(contains(
+        &replies,
+        "That address is already registered to another Duolingo account."
+    ));
+
+    let replies = harness.send(ALICE_CHAT, "/update alice").await.unwrap();
+    assert!(contains(&replies, "You need to earn more XP"));
+
+    harness.duolingo.set_total_xp(ALICE_UID, 130);
+    let replies = harness.send(ALICE_CHAT, "/update alice").await.unwrap();
+    assert!(contains(&replies, "you received 30 POD"));
+    let account = harness
+        .registry()
+        .account(ALICE_UID)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(account.address, Some(address));
+    assert_eq!(account.last_reported_xp, Some(130));
+    assert_eq!(
+        harness.user_in_contract(ALICE_UID).await,
+        (address, 130.into())
+    );
+    assert_eq!(
+        harness.contract.balance_of(address).await.unwrap(),
+        U256::exp10(18) * 30
+    );
+
+    harness.duolingo.set_total_xp(ALICE_UID, 150);
+    let replies = harness.send(ALICE_CHAT, "/check").await.unwrap();
+    assert!(contains(&replies, "you can mint 20 XP as POD"));
+
+    let replies = harness.send(ALICE_CHAT, "/unregister alice").await.unwrap();
+    assert!(contains(&replies, "You've been unregistered."));
+    assert_eq!(
+        harness.user_in_contract(ALICE_UID).await,
+        (Address::zero(), U256::zero())
+    );
+
+    // progress messages are cleaned up once each command finishes
+    assert!(!harness
+        .telegram
+        .visible(ALICE_CHAT)
+        .iter()
+        .any(|m| m.contains("loading your Duolingo profile")));
+}
+
+#[tokio::test]
+#[ignore = "requires anvil"]
+async fn test_minted_pod_metric() {
+    let harness = Harness::with_anvil([alice().with_bio(&format!("hola {ALICE_ADDRESS}"))]).await;
+    link_alice(&harness).await;
+    harness.send(ALICE_CHAT, "/register").await.unwrap();
+    harness.duolingo.set_total_xp(ALICE_UID, 130);
+    harness.send(ALICE_CHAT, "/update").await.unwrap();
+
+    let tx_hash = harness
+        .registry()
+        .account(ALICE_UID)
+        .await
+        .unwrap()
+        .unwrap()
+        .last_tx_hash
+        .unwrap();
+    let receipt = harness
+        .contract
+        .client()
+        .get_transaction_receipt(tx_hash)
+        .await
+        .unwrap()
+        .unwrap();
+
+    // a registry of its own, since the global one is shared between tests
+    let metrics = metrics::Metrics::new();
+    metrics.tx_settled(&TxOutcome::Confirmed(Box::new(receipt)));
+    assert_eq!(metrics.pod_minted(), 30.0);
+}
+
+#[tokio::test]
+#[ignore = "requires anvil"]
+async fn test_concurrent_updates_are_serialized() {
+    const BOB_CHAT: i64 = 7002;
+    const BOB_ADDRESS: &str = "0x8626f6940E2eb28930eFb4CeF49B2d1F2C9C1199";
+    let harness = Harness::with_anvil([alice(), MockUser::new(1002, "bob").with_total_xp(5)]).await;
+    link_alice(&harness).await;
+    link(&harness, BOB_CHAT, "bob", 1002, BOB_ADDRESS).await;
+
+    let (alice_replies, bob_replies) = tokio::join!(
+        harness.send(ALICE_CHAT, "/register"),
+        harness.send(BOB_CHAT, "/register"),
+    );
+    assert!(contains(&alice_replies.unwrap(), "Registered!"));
+    assert!(contains(&bob_replies.unwrap(), "Registered!"));
+
+    harness.duolingo.set_total_xp(ALICE_UID, 130);
+    harness.duolingo.set_total_xp(1002, 25);
+    let (alice_replies, bob_replies) = tokio::join!(
+        harness.send(ALICE_CHAT, "/update"),
+        harness.send(BOB_CHAT, "/update"),
+    );
+    // each chat hears about its own transaction only
+    let alice_replies = alice_replies.unwrap();
+    let bob_replies = bob_replies.unwrap();
+    assert!(contains(&alice_replies, "you received 30 POD"));
+    assert!(!contains(&alice_replies, "you received 20 POD"));
+    assert!(contains(&bob_replies, "you received 20 POD"));
+    assert!(!contains(&bob_replies, "you received 30 POD"));
+
+    assert_eq!(
+        harness.user_in_contract(1002).await,
+        (BOB_ADDRESS.parse().unwrap(), 25.into())
+    );
+}
+
+#[tokio::test]
+#[ignore = "requires anvil"]
+async fn test_queue_recovers_the_nonce_after_an_outside_transaction() {
+    let harness = Harness::with_anvil([alice()]).await;
+    link_alice(&harness).await;
+    harness.send(ALICE_CHAT, "/register").await.unwrap();
+
+    // another process sending from the owner wallet takes the queue's nonce
+    let client = harness.contract.client();
+    client
+        .send_transaction(TransactionRequest::pay(Address::zero(), 1), None)
+        .await
+        .unwrap()
+        .await
+        .unwrap();
+
+    harness.duolingo.set_total_xp(ALICE_UID, 130);
+    let replies = harness
+        .send(ALICE_CHAT, "/update")
+        .await
+        .unwrap_or_default();
+    assert!(!contains(&replies, "you received 30 POD"));
+
+    // the failed send made the queue fetch the nonce again
+    let replies = harness.send(ALICE_CHAT, "/update").await.unwrap();
+    assert!(contains(&replies, "you received 30 POD"));
+}
+
+#[tokio::test]
+#[ignore = "requires anvil"]
+async fn test_batched_xp_reports() {
+    const BOB_CHAT: i64 = 7002;
+    const BOB_ADDRESS: &str = "0x8626f6940E2eb28930eFb4CeF49B2d1F2C9C1199";
+    let harness = Harness::with_anvil([alice(), MockUser::new(1002, "bob").with_total_xp(5)])
+        .await
+        .with_xp_batch_window(Duration::from_millis(500));
+    link_alice(&harness).await;
+    link(&harness, BOB_CHAT, "bob", 1002, BOB_ADDRESS).await;
+    harness.send(ALICE_CHAT, "/register").await.unwrap();
+    harness.send(BOB_CHAT, "/register").await.unwrap();
+
+    harness.duolingo.set_total_xp(ALICE_UID, 130);
+    harness.duolingo.set_total_xp(1002, 25);
+    let (alice_replies, bob_replies) = tokio::join!(
+        harness.send(ALICE_CHAT, "/update"),
+        harness.send(BOB_CHAT, "/update"),
+    );
+    assert!(contains(&alice_replies.unwrap(), "you received 30 POD"));
+    assert!(contains(&bob_replies.unwrap(), "you received 20 POD"));
+
+    // both reports went out in the same transaction
+    let registry = harness.registry();
+    let alice = registry.account(ALICE_UID).await.unwrap().unwrap();
+    let bob = registry.account(1002).await.unwrap().unwrap();
+    assert_eq!(alice.last_tx_hash, bob.last_tx_hash);
+    assert_eq!(
+        harness.user_in_contract(1002).await,
+        (BOB_ADDRESS.parse().unwrap(), 25.into())
+    );
+}
+
+#[tokio::test]
+#[ignore = "requires anvil"]
+async fn test_low_owner_balance_holds_off_transactions() {
+    // more than anvil funds the owner with
+    let harness = Harness::with_anvil([alice()])
+        .await
+        .with_min_balance(U256::exp10(18) * 1_000_000);
+    link_alice(&harness).await;
+
+    let replies = harness.send(ALICE_CHAT, "/register").await.unwrap();
+    assert!(contains(&replies, "running low on funds"));
+    assert_eq!(
+        harness.user_in_contract(ALICE_UID).await,
+        (Address::zero(), 0.into())
+    );
+}
+
+#[tokio::test]
+#[ignore = "requires anvil"]
+async fn test_sync_reports_xp_for_registered_accounts() {
+    let harness = Harness::with_anvil([alice()]).await;
+    let address: Address = ALICE_ADDRESS.parse().unwrap();
+    link_alice(&harness).await;
+    harness.send(ALICE_CHAT, "/register").await.unwrap();
+
+    assert_eq!(harness.sync(true).await.unwrap(), 0);
+
+    harness.duolingo.set_total_xp(ALICE_UID, 140);
+    assert_eq!(harness.sync(true).await.unwrap(), 1);
+    assert_eq!(
+        harness.user_in_contract(ALICE_UID).await,
+        (address, 140.into())
+    );
+    assert!(contains(
+        &harness.telegram.visible(ALICE_CHAT),
+        "You received 40 POD for your progress on Duolingo!"
+    ));
+    assert_eq!(
+        harness
+            .registry()
+            .account(ALICE_UID)
+            .await
+            .unwrap()
+            .unwrap()
+            .last_reported_xp,
+        Some(140)
+    );
+}
+
+#[tokio::test]
+#[ignore = "requires anvil"]
+async fn test_sync_covers_uids_registered_without_the_bot() {
+    const CAROL_UID: u64 = 1003;
+    let carol: Address = "0x8626f6940E2eb28930eFb4CeF49B2d1F2C9C1199"
+        .parse()
+        .unwrap();
+    let harness = Harness::with_anvil([MockUser::new(CAROL_UID, "carol").with_total_xp(10)]).await;
+
+    harness
+        .contract
+        .user_register(CAROL_UID.into(), carol, 10.into())
+        .send()
+        .await
+        .unwrap()
+        .await
+        .unwrap();
+    indexer::catch_up(&harness.connections, 0, 1).await.unwrap();
+
+    harness.duolingo.set_total_xp(CAROL_UID, 25);
+    assert_eq!(harness.sync(true).await.unwrap(), 1);
+    assert_eq!(
+        harness.user_in_contract(CAROL_UID).await,
+        (carol, 25.into())
+    );
+}
+
+#[tokio::test]
+#[ignore = "requires anvil"]
+async fn test_indexer_follows_registrations_and_mints() {
+    let harness = Harness::with_anvil([alice()]).await;
+    let address: Address = ALICE_ADDRESS.parse().unwrap();
+    link_alice(&harness).await;
+    harness.send(ALICE_CHAT, "/register").await.unwrap();
+    harness.duolingo.set_total_xp(ALICE_UID, 130);
+    harness.send(ALICE_CHAT, "/update").await.unwrap();
+
+    assert_eq!(
+        indexer::catch_up(&harness.connections, 0, 1).await.unwrap(),
+        2
+    );
+    let user = harness
+        .registry()
+        .indexed_user(ALICE_UID)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(user.address, Some(address));
+    assert_eq!(user.minted, U256::exp10(18) * 30);
+
+    // picks up where it left off
+    harness.send(ALICE_CHAT, "/unregister").await.unwrap();
+    assert_eq!(
+        indexer::catch_up(&harness.connections, 0, 1).await.unwrap(),
+        1
+    );
+    assert!(harness.registry().indexed_users().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+#[ignore = "requires anvil"]
+async fn test_reconcile_reports_and_fixes_drift() {
+    const NEW_ADDRESS: &str = "0x8626f6940E2eb28930eFb4CeF49B2d1F2C9C1199";
+    let harness = Harness::with_anvil([alice().with_bio(&format!("hola {ALICE_ADDRESS}"))]).await;
+    link_alice(&harness).await;
+    harness.send(ALICE_CHAT, "/register").await.unwrap();
+    indexer::catch_up(&harness.connections, 0, 1).await.unwrap();
+
+    let replies = harness.send(ADMIN_CHAT, "/reconcile").await.unwrap();
+    assert!(contains(&replies, "Checked 1 UIDs, everything matches."));
+
+    harness
+        .duolingo
+        .set_bio(ALICE_UID, &format!("hola {NEW_ADDRESS}"));
+    harness.duolingo.set_total_xp(ALICE_UID, 300);
+
+    let replies = harness.send(ADMIN_CHAT, "/reconcile").await.unwrap();
+    assert!(contains(
+        &replies,
+        &format!("registered with {ALICE_ADDRESS}, but their profile has {NEW_ADDRESS}")
+    ));
+    assert!(contains(
+        &replies,
+        "300 XP on Duolingo, 200 more than was reported"
+    ));
+
+    // the address moves before the XP is reported, so POD goes to the new one
+    let report = reconcile::reconcile(&harness.connections, true, 100)
+        .await
+        .unwrap();
+    assert_eq!(report.findings.len(), 2);
+    assert!(report
+        .findings
+        .iter()
+        .all(|f| matches!(f.fix, Some(reconcile::Fix::Fixed { .. }))));
+    assert_eq!(
+        harness.user_in_contract(ALICE_UID).await,
+        (NEW_ADDRESS.parse().unwrap(), 300.into())
+    );
+
+    indexer::catch_up(&harness.connections, 0, 1).await.unwrap();
+    let report = reconcile::reconcile(&harness.connections, false, 100)
+        .await
+        .unwrap();
+    assert!(report.findings.is_empty());
+}
+
+#[tokio::test]
+#[ignore = "requires anvil"]
+async fn test_admin_commands() {
+    const NEW_ADDRESS: &str = "0x8626f6940E2eb28930eFb4CeF49B2d1F2C9C1199";
+    let harness = Harness::with_anvil([alice()]).await;
+    let connections = &harness.connections;
+    let alice_address: Address = ALICE_ADDRESS.parse().unwrap();
+    let new_address: Address = NEW_ADDRESS.parse().unwrap();
+
+    // no Telegram link needed
+    harness
+        .duolingo
+        .set_bio(ALICE_UID, &format!("hola {ALICE_ADDRESS}"));
+    let report = admin::register(connections, "alice".to_string())
+        .await
+        .unwrap();
+    assert!(matches!(report.result, TxResult::Confirmed { .. }));
+    assert_eq!(
+        harness.user_in_contract(ALICE_UID).await,
+        (alice_address, 100.into())
+    );
+    let account = harness
+        .registry()
+        .account(ALICE_UID)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(account.telegram_user_id, None);
+    assert_eq!(account.address, Some(alice_address));
+
+    let report = admin::register(connections, "alice".to_string())
+        .await
+        .unwrap();
+    assert!(matches!(report.result, TxResult::Skipped { .. }));
+
+    harness.duolingo.set_total_xp(ALICE_UID, 130);
+    let report = admin::report_xp(connections, "alice".to_string())
+        .await
+        .unwrap();
+    assert!(matches!(report.result, TxResult::Confirmed { .. }));
+    assert_eq!(
+        harness.user_in_contract(ALICE_UID).await,
+        (alice_address, 130.into())
+    );
+    let report = admin::report_xp(connections, "alice".to_string())
+        .await
+        .unwrap();
+    assert!(matches!(report.result, TxResult::Skipped { .. }));
+
+    let report = admin::update_address(connections, "alice".to_string(), Some(new_address))
+        .await
+        .unwrap();
+    assert!(matches!(report.result, TxResult::Confirmed { .. }));
+    assert_eq!(
+        harness.user_in_contract(ALICE_UID).await,
+        (new_address, 130.into())
+    );
+    let account = harness
+        .registry()
+        .account(ALICE_UID)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(account.address, Some(new_address));
+
+    let report = admin::unregister(connections, "alice".to_string())
+        .await
+        .unwrap();
+    assert!(matches!(report.result, TxResult::Confirmed { .. }));
+    assert_eq!(
+        harness.user_in_contract(ALICE_UID).await,
+        (Address::zero(), 0.into())
+    );
+    let report = admin::unregister(connections, "alice".to_string())
+        .await
+        .unwrap();
+    assert!(matches!(report.result, TxResult::Skipped { .. }));
+
+    assert!(
+        admin::update_address(connections, "alice".to_string(), None)
+            .await
+            .is_err()
+    );
+}
+
+#[tokio::test]
+#[ignore = "requires anvil"]
+async fn test_admin_register_reports_reverts() {
+    const BOB_CHAT: i64 = 7002;
+    let harness = Harness::with_anvil([alice(), MockUser::new(1002, "bob").with_total_xp(5)]).await;
+    let connections = &harness.connections;
+    link_alice(&harness).await;
+    link(&harness, BOB_CHAT, "bob", 1002, ALICE_ADDRESS).await;
+
+    admin::register(connections, "alice".to_string())
+        .await
+        .unwrap();
+    let report = admin::register(connections, "bob".to_string())
+        .await
+        .unwrap();
+    assert!(matches!(
+        &report.result,
+        TxResult::Reverted { reason: Some(reason), .. } if reason == "Address is already registered"
+    ));
+}
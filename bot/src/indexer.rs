@@ -0,0 +1,230 @@
+use std::{sync::Arc, time::Duration};
+
+use ethers::{contract::LogMeta, providers::Middleware};
+
+use crate::{
+    registry::{ContractEvent, LoggedEvent},
+    Connections, DuolingoPowContractEvents, TransferFilter,
+};
+
+/// How many blocks are fetched per `eth_getLogs`, which RPCs tend to limit.
+const BATCH_BLOCKS: u64 = 2_000;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(12);
+
+/// Backfills the contract's events from `start_block`, or from wherever a
+/// previous run left off, then keeps following new blocks.
+pub fn spawn(connections: Arc<Connections>, start_block: u64, confirmations: usize) {
+    tokio::spawn(async move {
+        loop {
+            match catch_up(&connections, start_block, confirmations).await {
+                Ok(0) => {}
+                Ok(indexed) => log::info!("Indexed {indexed} contract events"),
+                Err(e) => log::error!("Indexing contract events failed: {e:#}"),
+            }
+
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    });
+}
+
+/// Indexes every block up to the last one with `confirmations`, so reorgs
+/// shallower than that never reach the index. Returns the number of events
+/// indexed.
+pub async fn catch_up(
+    connections: &Connections,
+    start_block: u64,
+    confirmations: usize,
+) -> anyhow::Result<usize> {
+    let contract = &connections.contract;
+    let registry = &connections.registry;
+
+    let mut from = registry
+        .index_cursor(contract.address())
+        .await?
+        .unwrap_or(start_block);
+    let head = contract
+        .client()
+        .get_block_number()
+        .await?
+        .as_u64()
+        .saturating_sub(confirmations.saturating_sub(1) as u64);
+
+    let mut indexed = 0;
+    while from <= head {
+        let to = head.min(from + BATCH_BLOCKS - 1);
+
+        let logs = contract
+            .events()
+            .from_block(from)
+            .to_block(to)
+            .query_with_meta()
+            .await?;
+        let events = contract_events(logs);
+
+        registry
+            .index_events(contract.address(), &events, to + 1)
+            .await?;
+
+        indexed += events.len();
+        from = to + 1;
+    }
+
+    Ok(indexed)
+}
+
+/// Picks out registration updates and mints. `_mintXp` emits the mint's
+/// `Transfer` and then `XpReported` in the same transaction, which is what
+/// ties the POD to a UID; the address alone doesn't, since two UIDs can
+/// share one. Mints without a report right after them are left for the
+/// registry to attribute by address.
+fn contract_events(logs: Vec<(DuolingoPowContractEvents, LogMeta)>) -> Vec<LoggedEvent> {
+    fn logged(meta: &LogMeta, event: ContractEvent) -> LoggedEvent {
+        LoggedEvent {
+            block_number: meta.block_number.as_u64(),
+            log_index: meta.log_index.as_u64(),
+            tx_hash: meta.transaction_hash,
+            event,
+        }
+    }
+
+    let mut events = Vec::new();
+    let mut last_mint: Option<(LogMeta, TransferFilter)> = None;
+
+    for (event, meta) in logs {
+        if let Some((mint_meta, mint)) = last_mint.take() {
+            let uid = match &event {
+                DuolingoPowContractEvents::XpReportedFilter(reported)
+                    if mint_meta.transaction_hash == meta.transaction_hash
+                        && mint_meta.log_index + 1 == meta.log_index =>
+                {
+                    u64::try_from(reported.uid).ok()
+                }
+                _ => None,
+            };
+            events.push(logged(
+                &mint_meta,
+                ContractEvent::Mint {
+                    uid,
+                    to: mint.to,
+                    amount: mint.value,
+                },
+            ));
+        }
+
+        match event {
+            DuolingoPowContractEvents::UserRegistrationUpdateFilter(event) => {
+                let Ok(uid) = u64::try_from(event.uid) else {
+                    continue;
+                };
+                events.push(logged(
+                    &meta,
+                    ContractEvent::RegistrationUpdate {
+                        uid,
+                        address: event.address,
+                    },
+                ));
+            }
+            DuolingoPowContractEvents::TransferFilter(event) if event.from.is_zero() => {
+                last_mint = Some((meta, event));
+            }
+            _ => {}
+        }
+    }
+
+    if let Some((mint_meta, mint)) = last_mint {
+        events.push(logged(
+            &mint_meta,
+            ContractEvent::Mint {
+                uid: None,
+                to: mint.to,
+                amount: mint.value,
+            },
+        ));
+    }
+
+    events
+}
+
+#[test]
+fn test_mints_are_attributed_by_the_following_xp_report() {
+    use ethers::types::{Address, TxHash, U256};
+
+    use crate::XpReportedFilter;
+
+    let shared = Address::repeat_byte(0xaa);
+    let meta = |tx: u8, log_index: u64| LogMeta {
+        address: Address::zero(),
+        block_number: 10.into(),
+        block_hash: Default::default(),
+        transaction_hash: TxHash::repeat_byte(tx),
+        transaction_index: 0.into(),
+        log_index: log_index.into(),
+    };
+    let mint = |value: u64| {
+        DuolingoPowContractEvents::TransferFilter(TransferFilter {
+            from: Address::zero(),
+            to: shared,
+            value: value.into(),
+        })
+    };
+    let reported = |uid: u64| {
+        DuolingoPowContractEvents::XpReportedFilter(XpReportedFilter {
+            uid: uid.into(),
+            xp: U256::zero(),
+        })
+    };
+
+    let events = contract_events(vec![
+        // a batch paying two UIDs that share an address
+        (mint(20), meta(1, 0)),
+        (reported(1002), meta(1, 1)),
+        (mint(30), meta(1, 2)),
+        (reported(1001), meta(1, 3)),
+        // a mint without a report, as from the deployed contract
+        (mint(5), meta(2, 4)),
+        (reported(1001), meta(3, 5)),
+        (mint(7), meta(4, 6)),
+    ]);
+
+    assert_eq!(
+        events
+            .iter()
+            .map(|e| (e.log_index, e.event.clone()))
+            .collect::<Vec<_>>(),
+        vec![
+            (
+                0,
+                ContractEvent::Mint {
+                    uid: Some(1002),
+                    to: shared,
+                    amount: 20.into(),
+                }
+            ),
+            (
+                2,
+                ContractEvent::Mint {
+                    uid: Some(1001),
+                    to: shared,
+                    amount: 30.into(),
+                }
+            ),
+            (
+                4,
+                ContractEvent::Mint {
+                    uid: None,
+                    to: shared,
+                    amount: 5.into(),
+                }
+            ),
+            (
+                6,
+                ContractEvent::Mint {
+                    uid: None,
+                    to: shared,
+                    amount: 7.into(),
+                }
+            ),
+        ]
+    );
+}
@@ -0,0 +1,114 @@
+use base64::{prelude::BASE64_URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, TimeZone, Utc};
+use serde::Deserialize;
+
+#[derive(Debug, thiserror::Error)]
+pub enum JwtError {
+    #[error("That doesn't look like a JWT")]
+    Malformed,
+    #[error("That JWT couldn't be decoded")]
+    Encoding(#[from] base64::DecodeError),
+    #[error("That JWT doesn't name a Duolingo account")]
+    Claims(#[from] serde_json::Error),
+    #[error("That JWT expired on {0}")]
+    Expired(DateTime<Utc>),
+    #[error("That JWT belongs to a different Duolingo account")]
+    WrongAccount { expected: u64, found: u64 },
+}
+
+/// The parts of a Duolingo JWT the bot cares about. The signature isn't
+/// checked here; Duolingo does that when the token is used.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Claims {
+    pub sub: u64,
+    #[serde(default)]
+    pub exp: Option<i64>,
+}
+
+impl Claims {
+    pub fn expect_sub(&self, uid: u64) -> Result<(), JwtError> {
+        if self.sub == uid {
+            Ok(())
+        } else {
+            Err(JwtError::WrongAccount {
+                expected: uid,
+                found: self.sub,
+            })
+        }
+    }
+}
+
+pub fn parse(token: &str) -> Result<Claims, JwtError> {
+    let [_header, payload, _signature] = token
+        .trim()
+        .split('.')
+        .collect::<Vec<_>>()
+        .try_into()
+        .map_err(|_| JwtError::Malformed)?;
+
+    // JWTs are unpadded, but tolerate padding added by a copy-paste
+    let payload = BASE64_URL_SAFE_NO_PAD.decode(payload.trim_end_matches('='))?;
+    let claims: Claims = serde_json::from_slice(&payload)?;
+
+    if let Some(exp) = claims.exp {
+        let expires_at = Utc
+            .timestamp_opt(exp, 0)
+            .single()
+            .ok_or(JwtError::Malformed)?;
+
+        if expires_at <= Utc::now() {
+            return Err(JwtError::Expired(expires_at));
+        }
+    }
+
+    Ok(claims)
+}
+
+#[cfg(test)]
+fn token(claims: serde_json::Value) -> String {
+    format!(
+        "eyJhbGciOiJIUzI1NiJ9.{}.c2ln",
+        BASE64_URL_SAFE_NO_PAD.encode(claims.to_string())
+    )
+}
+
+#[test]
+fn test_parse() {
+    use serde_json::json;
+
+    let exp = Utc::now().timestamp() + 3600;
+    let claims = parse(&token(json!({ "sub": 1001, "exp": exp }))).unwrap();
+    assert_eq!(
+        claims,
+        Claims {
+            sub: 1001,
+            exp: Some(exp)
+        }
+    );
+    assert!(claims.expect_sub(1001).is_ok());
+    assert!(matches!(
+        claims.expect_sub(1002),
+        Err(JwtError::WrongAccount {
+            expected: 1002,
+            found: 1001
+        })
+    ));
+
+    // this payload encodes with a `-`, which standard base64 rejects
+    assert!(parse(&token(json!({ "sub": 1, "iss": "?>?" }))).is_ok());
+    let padded = base64::prelude::BASE64_URL_SAFE.encode(r#"{"sub":12}"#);
+    assert!(padded.ends_with('='));
+    assert_eq!(parse(&format!("h.{padded}.s")).unwrap().sub, 12);
+
+    assert!(matches!(parse("hello"), Err(JwtError::Malformed)));
+    assert!(matches!(parse("a.b.c.d"), Err(JwtError::Malformed)));
+    assert!(matches!(parse("a.!!.c"), Err(JwtError::Encoding(_))));
+    assert!(matches!(
+        parse(&token(json!({ "name": "alice" }))),
+        Err(JwtError::Claims(_))
+    ));
+    assert!(matches!(
+        parse(&token(json!({ "sub": 1, "exp": 1_000_000_000 }))),
+        Err(JwtError::Expired(_))
+    ));
+}
@@ -1,7 +1,14 @@
-use std::{path::PathBuf, sync::Arc, time::Duration};
+use std::{
+    net::{SocketAddr, TcpListener},
+    ops::ControlFlow,
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
 
+use anyhow::Context;
 use clap::{Parser, Subcommand};
-use dptree::{case, deps};
+use dptree::{case, deps, di::DependencySupplier};
 use ethers::{
     contract::abigen,
     core::k256::ecdsa::SigningKey,
@@ -11,19 +18,50 @@ use ethers::{
     types::{Address, U256},
 };
 use log::Level;
-use once_cell::sync::Lazy;
 use reqwest::Url;
 use serde::{Deserialize, Serialize};
-use serde_json::json;
 use teloxide::{
-    dispatching::{
-        dialogue::{self, InMemStorage},
-        UpdateHandler,
-    },
+    dispatching::{dialogue, DefaultKey, UpdateHandler},
     prelude::*,
     types::ParseMode,
-    utils::command::BotCommands,
+    utils::command::{BotCommands, ParseError},
+};
+
+use crate::config::{ChainConfig, Config, DuolingoConfig, RunArgs, StorageConfig};
+use crate::duolingo::{
+    add_address_to_profile, address_in_bio, get_user_uid_and_maybe_address, DuolingoApi,
+    DuolingoClient,
 };
+use crate::error::BotError;
+use crate::mock_duolingo::{MockDuolingo, MockUser};
+use crate::registry::Registry;
+use crate::status::StatusMessages;
+use crate::storage::{open_dialogue_storage, DialogueStorage};
+use crate::tx::{minted_pod, tx_link, TxOutcome};
+use crate::tx_queue::{OwnerTx, TxQueue};
+
+mod admin;
+mod balance;
+mod config;
+mod deploy;
+mod duolingo;
+mod error;
+#[cfg(test)]
+mod fake_telegram;
+#[cfg(test)]
+mod harness;
+mod indexer;
+mod jwt;
+mod metrics;
+mod mock_duolingo;
+mod reconcile;
+mod registry;
+mod status;
+mod storage;
+mod sync;
+mod tx;
+mod tx_queue;
+mod webhook;
 
 const USER_AGENT: &str = concat!("duopow-bot/", env!("CARGO_PKG_VERSION"));
 
@@ -32,9 +70,6 @@ abigen!(
     "../contract/out/DuolingoPow.sol/DuolingoPow.json"
 );
 
-static ETH_ADDRESS: Lazy<regex::Regex> =
-    Lazy::new(|| regex::Regex::new(r"0x[0-9a-fA-F]{40}").unwrap());
-
 #[derive(Parser)]
 struct Args {
     #[clap(subcommand)]
@@ -42,6 +77,7 @@ struct Args {
 }
 
 #[derive(Subcommand)]
+#[allow(clippy::large_enum_variant)]
 enum Command {
     GenerateKeystore {
         #[clap(short, long, default_value = "./keystore/")]
@@ -50,74 +86,19 @@ enum Command {
         #[clap(short, long, env = "DUOPOW_PASSWORD", default_value = "")]
         password: String,
     },
-    // UpdateProfile {
-    //     address: Address,
-
-    //     #[clap(short, long, env = "DUOPOW_JWT")]
-    //     jwt: String,
-    // },
-    Run {
-        #[clap(short, long, env = "DUOPOW_KEYSTORE")]
-        keystore: PathBuf,
-
-        #[clap(short, long, env = "DUOPOW_PASSWORD", default_value = "")]
-        password: String,
-
-        #[clap(short, long, env = "DUOPOW_TG_TOKEN")]
-        tg_token: String,
-
-        #[clap(short, long, env = "DUOPOW_CONTRACT")]
-        contract: Address,
-
-        #[clap(short, long, env = "DUOPOW_RPC")]
-        rpc: Url,
+    Run(RunArgs),
+    Deploy(deploy::DeployArgs),
+    #[clap(flatten)]
+    Admin(admin::AdminCommand),
+    MockDuolingo {
+        #[clap(short, long, default_value = "127.0.0.1:8081")]
+        listen_addr: SocketAddr,
+
+        #[clap(short, long)]
+        users: Option<PathBuf>,
     },
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct UserResponse {
-    streak: u32,
-    id: u64,
-    username: String,
-    bio: String,
-    name: String,
-    courses: Vec<CourseResponse>,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct CourseResponse {
-    title: String,
-    learning_language: String,
-    xp: u64,
-    from_language: String,
-    id: String,
-}
-
-async fn get_user_by_username(
-    http: &reqwest::Client,
-    username: &str,
-) -> anyhow::Result<UserResponse> {
-    #[derive(Deserialize)]
-    struct UserRequestResponse {
-        users: Vec<UserResponse>,
-    }
-
-    let mut response = http
-        .get("https://www.duolingo.com/2017-06-30/users")
-        .query(&[("username", username)])
-        .send()
-        .await?
-        .json::<UserRequestResponse>()
-        .await?;
-
-    if let Some(user) = response.users.pop() {
-        Ok(user)
-    } else {
-        anyhow::bail!("User not found")
-    }
-}
-
 #[derive(BotCommands, Clone)]
 #[command(
     rename_rule = "lowercase",
@@ -129,64 +110,67 @@ enum BotCommand {
     #[command(description = "link your Duolingo and Taiko accounts (do this first)")]
     Link,
     #[command(
-        description = "[username] register your Duolingo account with the smart contract (do this second)"
+        description = "link by signing a message with your wallet instead of adding your address to your Duolingo bio"
     )]
-    Register { username: String },
-    #[command(description = "[username] unregister your Duolingo account")]
-    Unregister { username: String },
-    #[command(description = "[username] update your XP and mint your rewards")]
-    Update { username: String },
-    #[command(description = "[username] view an account")]
-    Check { username: String },
+    LinkWallet,
+    #[command(
+        description = "[username] register your Duolingo account with the smart contract (do this second)",
+        parse_with = parse_optional_username
+    )]
+    Register { username: Option<String> },
+    #[command(
+        description = "[username] unregister your Duolingo account",
+        parse_with = parse_optional_username
+    )]
+    Unregister { username: Option<String> },
+    #[command(
+        description = "[username] update your XP and mint your rewards",
+        parse_with = parse_optional_username
+    )]
+    Update { username: Option<String> },
+    #[command(
+        description = "[username] view an account (yours if no username is given)",
+        parse_with = parse_optional_username
+    )]
+    Check { username: Option<String> },
     #[command(description = "cancel")]
     Cancel,
+    // `/reconcile [fix]`, which only admins can use, so it's left out of
+    // /help
+    #[command(description = "off", parse_with = parse_reconcile_args)]
+    Reconcile { fix: bool },
 }
 
-async fn get_user_total_xp(http: &reqwest::Client, uid: u64) -> anyhow::Result<u64> {
-    #[derive(Deserialize)]
-    #[serde(rename_all = "camelCase")]
-    struct TotalXp {
-        total_xp: u64,
+impl BotCommand {
+    fn name(&self) -> &'static str {
+        match self {
+            BotCommand::Help => "help",
+            BotCommand::Link => "link",
+            BotCommand::LinkWallet => "linkwallet",
+            BotCommand::Register { .. } => "register",
+            BotCommand::Unregister { .. } => "unregister",
+            BotCommand::Update { .. } => "update",
+            BotCommand::Check { .. } => "check",
+            BotCommand::Cancel => "cancel",
+            BotCommand::Reconcile { .. } => "reconcile",
+        }
     }
-
-    Ok(http
-        .get(format!("https://www.duolingo.com/2017-06-30/users/{uid}"))
-        .query(&[("fields", "totalXp")])
-        .send()
-        .await?
-        .json::<TotalXp>()
-        .await?
-        .total_xp)
 }
 
-async fn get_user_uid_and_maybe_address(
-    http: &reqwest::Client,
-    username: &str,
-) -> Option<(u64, Option<Address>)> {
-    let response = get_user_by_username(http, username).await.ok()?;
-
-    let uid = response.id;
-
-    let address_match = ETH_ADDRESS.find(&response.bio)?;
+fn parse_optional_username(input: String) -> Result<(Option<String>,), ParseError> {
+    let username = input.trim();
 
-    let address: Option<Address> = address_match.as_str().parse().ok();
-
-    Some((uid, address))
+    Ok(((!username.is_empty()).then(|| username.to_owned()),))
 }
 
-async fn get_user_uid_and_address(
-    http: &reqwest::Client,
-    username: &str,
-) -> Option<(u64, Address)> {
-    let response = get_user_by_username(http, username).await.ok()?;
-
-    let uid = response.id;
-
-    let address_match = ETH_ADDRESS.find(&response.bio)?;
-
-    let address: Address = address_match.as_str().parse().ok()?;
-
-    Some((uid, address))
+fn parse_reconcile_args(input: String) -> Result<(bool,), ParseError> {
+    match input.trim() {
+        "" => Ok((false,)),
+        "fix" => Ok((true,)),
+        other => Err(ParseError::IncorrectFormat(
+            format!("expected `fix` or nothing, got {other:?}").into(),
+        )),
+    }
 }
 
 #[tokio::main]
@@ -205,18 +189,21 @@ async fn main() {
             )
             .unwrap();
         }
-        Command::Run {
-            keystore,
-            password,
-            contract,
-            tg_token,
-            rpc,
-        } => {
+        Command::Run(args) => {
             pretty_env_logger::init();
+
+            let config = match Config::load(args) {
+                Ok(config) => config,
+                Err(e) => {
+                    eprintln!("Error: {e:?}");
+                    std::process::exit(2);
+                }
+            };
+
             log::info!("Starting bot");
 
             let bot = Bot::with_client(
-                tg_token,
+                config.telegram.token,
                 reqwest::Client::builder()
                     .user_agent(USER_AGENT)
                     .tcp_keepalive(Duration::from_secs(60))
@@ -224,91 +211,556 @@ async fn main() {
                     .unwrap(),
             );
 
-            let wallet = Wallet::decrypt_keystore(keystore, password).unwrap();
-
-            let http = reqwest::Client::builder()
-                .user_agent(USER_AGENT)
-                .build()
+            let storage = open_dialogue_storage(config.storage.kind, &config.storage.db)
+                .await
                 .unwrap();
 
-            let provider =
-                ethers::providers::Provider::<ethers::providers::Http>::try_from(rpc.as_str())
+            let connections = Arc::new(Connections {
+                admins: config.telegram.admins,
+                ..connect(
+                    &config.chain,
+                    config.duolingo,
+                    &config.storage,
+                    config.features.xp_batch_window,
+                )
+                .await
+                .unwrap()
+            });
+
+            if let Some(addr) = config.features.metrics_addr {
+                log::info!("Serving metrics on {addr}");
+                metrics::serve(TcpListener::bind(addr).unwrap(), connections.clone()).unwrap();
+            }
+
+            if let Some(interval) = config.features.sync_interval {
+                sync::spawn(
+                    bot.clone(),
+                    connections.clone(),
+                    interval,
+                    config.features.sync_notify,
+                );
+            }
+
+            if let Some(min_balance) = config.chain.min_balance {
+                balance::spawn(
+                    bot.clone(),
+                    connections.clone(),
+                    min_balance,
+                    config.telegram.alert_chat,
+                );
+            }
+
+            if let Some(start_block) = config.features.index_from_block {
+                indexer::spawn(connections.clone(), start_block, config.chain.confirmations);
+            }
+
+            let mut dispatcher = dispatcher(bot.clone(), connections, storage);
+
+            match config.telegram.webhook {
+                Some(webhook) => {
+                    log::info!("Listening for webhook updates on {}", webhook.listen_addr);
+                    let listener = webhook::listen(
+                        bot,
+                        webhook.url,
+                        TcpListener::bind(webhook.listen_addr).unwrap(),
+                        webhook.secret,
+                    )
+                    .await
                     .unwrap();
 
-            let chain_id = provider.get_chainid().await.unwrap().as_u64();
+                    dispatcher
+                        .dispatch_with_listener(
+                            listener,
+                            LoggingErrorHandler::with_custom_text(
+                                "An error from the webhook listener",
+                            ),
+                        )
+                        .await;
+                }
+                None => dispatcher.dispatch().await,
+            }
+        }
+        Command::Deploy(args) => {
+            pretty_env_logger::init();
 
-            let duo = DuolingoPowContract::new(
-                contract,
-                Arc::new(SignerMiddleware::new(
-                    provider,
-                    wallet.with_chain_id(chain_id),
-                )),
-            );
+            if let Err(e) = deploy::run(args).await {
+                eprintln!("Error: {e:?}");
+                std::process::exit(1);
+            }
+        }
+        Command::Admin(command) => {
+            pretty_env_logger::init();
 
-            Dispatcher::builder(bot, handler())
-                .dependencies(deps![
-                    Arc::new(Connections {
-                        http,
-                        contract: duo,
-                        contract_address: contract
-                    }),
-                    InMemStorage::<ChatState>::new()
-                ])
-                .error_handler(LoggingErrorHandler::with_custom_text(
-                    "An error has occurred in the dispatcher",
-                ))
-                .enable_ctrlc_handler()
-                .build()
-                .dispatch()
-                .await;
+            if let Err(e) = admin::run(command).await {
+                eprintln!("Error: {e:?}");
+                std::process::exit(1);
+            }
+        }
+        Command::MockDuolingo { listen_addr, users } => {
+            pretty_env_logger::init();
+
+            let users: Vec<MockUser> = match users {
+                Some(path) => serde_json::from_slice(&std::fs::read(path).unwrap()).unwrap(),
+                None => vec![],
+            };
+
+            for user in &users {
+                log::info!(
+                    "Seeded user {} ({}) with JWT {}",
+                    user.username,
+                    user.id,
+                    mock_duolingo::jwt_for(user.id),
+                );
+            }
+
+            log::info!("Mock Duolingo listening on {listen_addr}");
+
+            MockDuolingo::new(users)
+                .serve(std::net::TcpListener::bind(listen_addr).unwrap())
+                .await
+                .unwrap();
         }
     }
 }
 
-#[derive(Clone, Default)]
+/// How a user proves they control the address they're linking.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+enum AddressProof {
+    /// The address goes in their Duolingo bio, next to a challenge code to
+    /// show the account is theirs. Or the bot writes it there using their JWT.
+    #[default]
+    Bio,
+    /// They sign a message with the address's key, then show they control the
+    /// Duolingo account by putting a challenge code in their bio.
+    Signature,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
 enum ChatState {
     #[default]
     Start,
-    LinkReceiveUsername,
+    LinkReceiveUsername {
+        #[serde(default)]
+        proof: AddressProof,
+    },
     LinkReceiveAddress {
         username: String,
+        #[serde(default)]
+        proof: AddressProof,
     },
-    LinkReceiveJwt {
+    LinkReceiveSignature {
         username: String,
         address: Address,
+        message: String,
+    },
+    /// With `AddressProof::Bio`, a JWT is accepted here instead of the code.
+    /// The JWT itself is never stored in the dialogue state, because the
+    /// state may be persisted to disk.
+    LinkAwaitChallenge {
+        username: String,
+        address: Address,
+        code: String,
+        proof: AddressProof,
     },
 }
 
+type ChatDialogue = Dialogue<ChatState, DialogueStorage>;
+
+type OwnerMiddleware =
+    SignerMiddleware<ethers::providers::Provider<ethers::providers::Http>, Wallet<SigningKey>>;
+
+type OwnerContract = DuolingoPowContract<OwnerMiddleware>;
+
 struct Connections {
-    http: reqwest::Client,
-    contract: DuolingoPowContract<
-        SignerMiddleware<ethers::providers::Provider<ethers::providers::Http>, Wallet<SigningKey>>,
-    >,
+    duolingo: Box<dyn DuolingoApi>,
+    contract: OwnerContract,
     contract_address: Address,
+    registry: Registry,
+    tx_queue: TxQueue,
+    explorer_url: Url,
+    /// Telegram users allowed to use admin commands.
+    admins: Vec<UserId>,
 }
 
-fn handler() -> UpdateHandler<anyhow::Error> {
-    dialogue::enter::<Update, InMemStorage<ChatState>, _, _>().branch(
-        Update::filter_message()
-            .branch(
-                teloxide::filter_command::<BotCommand, _>().branch(
-                    case![ChatState::Start]
-                        .branch(case![BotCommand::Help].endpoint(help))
-                        .branch(case![BotCommand::Cancel].endpoint(cancel))
-                        .branch(case![BotCommand::Link].endpoint(begin_link))
-                        .branch(case![BotCommand::Register { username }].endpoint(register))
-                        .branch(case![BotCommand::Update { username }].endpoint(update))
-                        .branch(case![BotCommand::Check { username }].endpoint(check))
-                        .branch(case![BotCommand::Unregister { username }].endpoint(unregister)),
-                ),
+// Each of these records the change in the registry once it's confirmed.
+impl Connections {
+    async fn register(&self, uid: u64, address: Address, xp: u64) -> anyhow::Result<TxOutcome> {
+        let outcome = self
+            .tx_queue
+            .submit(OwnerTx::Register { uid, address, xp })
+            .await?;
+
+        if let TxOutcome::Confirmed(receipt) = &outcome {
+            self.registry
+                .record_registration(uid, address, xp, receipt.transaction_hash)
+                .await?;
+        }
+
+        Ok(outcome)
+    }
+
+    async fn update_address(&self, uid: u64, address: Address) -> anyhow::Result<TxOutcome> {
+        let outcome = self
+            .tx_queue
+            .submit(OwnerTx::UpdateAddress { uid, address })
+            .await?;
+
+        if let TxOutcome::Confirmed(receipt) = &outcome {
+            self.registry
+                .record_address(uid, address, receipt.transaction_hash)
+                .await?;
+        }
+
+        Ok(outcome)
+    }
+
+    async fn report_xp(&self, uid: u64, xp: u64) -> anyhow::Result<TxOutcome> {
+        let outcome = self.tx_queue.submit(OwnerTx::ReportXp { uid, xp }).await?;
+
+        if let TxOutcome::Confirmed(receipt) = &outcome {
+            self.registry
+                .record_xp(uid, xp, receipt.transaction_hash)
+                .await?;
+        }
+
+        Ok(outcome)
+    }
+
+    async fn unregister(&self, uid: u64) -> anyhow::Result<TxOutcome> {
+        let outcome = self.tx_queue.submit(OwnerTx::Unregister { uid }).await?;
+
+        if let TxOutcome::Confirmed(receipt) = &outcome {
+            self.registry
+                .record_unregistration(uid, receipt.transaction_hash)
+                .await?;
+        }
+
+        Ok(outcome)
+    }
+}
+
+/// The keystore wallet, signing for whichever chain the RPC is on.
+async fn owner_client(chain: &ChainConfig) -> anyhow::Result<Arc<OwnerMiddleware>> {
+    let wallet = Wallet::decrypt_keystore(&chain.keystore, &chain.password).with_context(|| {
+        format!(
+            "Failed to decrypt the keystore {}",
+            chain.keystore.to_string_lossy()
+        )
+    })?;
+
+    let provider =
+        ethers::providers::Provider::<ethers::providers::Http>::try_from(chain.rpc.as_str())?;
+    let chain_id = provider.get_chainid().await?.as_u64();
+
+    Ok(Arc::new(SignerMiddleware::new(
+        provider,
+        wallet.with_chain_id(chain_id),
+    )))
+}
+
+fn duolingo_client(config: DuolingoConfig) -> anyhow::Result<DuolingoClient> {
+    let http = reqwest::Client::builder()
+        .user_agent(config.user_agent)
+        .timeout(config.timeout)
+        .build()?;
+
+    let client = DuolingoClient::new(http, config.base_url);
+
+    Ok(match config.rate_limit {
+        Some(per_second) => client.with_rate_limit(per_second),
+        None => client,
+    })
+}
+
+async fn connect(
+    chain: &ChainConfig,
+    duolingo: DuolingoConfig,
+    storage: &StorageConfig,
+    xp_batch_window: Option<Duration>,
+) -> anyhow::Result<Connections> {
+    let contract_address = chain.contract()?;
+    let contract = DuolingoPowContract::new(contract_address, owner_client(chain).await?);
+    let tx_queue = TxQueue::spawn(
+        contract.clone(),
+        chain.confirmations,
+        chain.tx_retries,
+        chain.max_gas_price,
+        chain.min_balance,
+        xp_batch_window,
+    );
+
+    Ok(Connections {
+        duolingo: Box::new(duolingo_client(duolingo)?),
+        contract,
+        contract_address,
+        registry: Registry::open(&storage.db).await?,
+        tx_queue,
+        explorer_url: chain.explorer_url.clone(),
+        admins: vec![],
+    })
+}
+
+const HELD_BACK_MESSAGE: &str =
+    "Rewards are held back until you earn past your previous high, so no XP is paid out twice.";
+
+/// How Duolingo XP compares to the high-water mark stored in the contract.
+/// XP can go down, e.g. when a course is removed, in which case nothing is
+/// minted until it recovers.
+#[derive(Debug, PartialEq, Eq)]
+enum XpProgress {
+    Gained(u64),
+    Unchanged,
+    Decreased(u64),
+}
+
+impl XpProgress {
+    fn new(total_xp: u64, xp_in_contract: U256) -> Self {
+        let total_xp = U256::from(total_xp);
+
+        match total_xp.cmp(&xp_in_contract) {
+            std::cmp::Ordering::Greater => Self::Gained((total_xp - xp_in_contract).low_u64()),
+            std::cmp::Ordering::Equal => Self::Unchanged,
+            std::cmp::Ordering::Less => {
+                Self::Decreased((xp_in_contract - total_xp).try_into().unwrap_or(u64::MAX))
+            }
+        }
+    }
+}
+
+fn no_address() -> BotError {
+    BotError::Validation(
+        "There's no address linked to this Duolingo profile. Use /link to add one.".to_string(),
+    )
+}
+
+const NOT_OWNER_MESSAGE: &str = "Only the Telegram account that linked this Duolingo profile can do that. Use /link to prove that it's yours.";
+
+/// Looks up `username` on Duolingo along with the address to trust for it:
+/// the one proven by signature if there is one, otherwise the one in the bio.
+async fn find_account(
+    connections: &Connections,
+    username: &str,
+) -> anyhow::Result<Option<(u64, Option<Address>)>> {
+    let Some((uid, address_in_bio)) =
+        get_user_uid_and_maybe_address(&*connections.duolingo, username).await?
+    else {
+        return Ok(None);
+    };
+
+    let verified_address = connections
+        .registry
+        .account(uid)
+        .await?
+        .and_then(|account| account.verified_address);
+
+    Ok(Some((uid, verified_address.or(address_in_bio))))
+}
+
+/// Who a command is about. The sender's own account is followed by UID, so
+/// that it still resolves after they change their Duolingo username.
+enum Target {
+    Username(String),
+    Linked { uid: u64, username: String },
+}
+
+impl Target {
+    async fn find(&self, connections: &Connections) -> Result<(u64, Option<Address>), BotError> {
+        match self {
+            Target::Username(username) => Ok(find_account(connections, username)
+                .await?
+                .ok_or_else(|| BotError::UserNotFound(username.clone()))?),
+            Target::Linked { uid, username } => {
+                let profile = connections
+                    .duolingo
+                    .get_public_profile(*uid)
+                    .await?
+                    .ok_or_else(|| BotError::UserNotFound(username.clone()))?;
+
+                let verified_address = connections
+                    .registry
+                    .account(*uid)
+                    .await?
+                    .and_then(|account| account.verified_address);
+
+                Ok((*uid, verified_address.or(profile.address())))
+            }
+        }
+    }
+}
+
+/// Falls back to the sender's linked account when no username was given.
+/// Replies with an explanation and returns `None` if that isn't possible.
+async fn resolve_target(
+    bot: &Bot,
+    msg: &Message,
+    connections: &Connections,
+    username: Option<String>,
+) -> anyhow::Result<Option<Target>> {
+    if let Some(username) = username {
+        return Ok(Some(Target::Username(username)));
+    }
+
+    let accounts = match msg.from() {
+        Some(user) => {
+            connections
+                .registry
+                .accounts_for_telegram_user(user.id)
+                .await?
+        }
+        None => vec![],
+    };
+
+    match &accounts[..] {
+        [account] => Ok(Some(Target::Linked {
+            uid: account.duolingo_uid,
+            username: account.username.clone(),
+        })),
+        [] => {
+            bot.send_message(
+                msg.chat.id,
+                "Please give a Duolingo username, or /link your account first.",
             )
-            .branch(case![ChatState::LinkReceiveUsername].endpoint(link_receive_username))
-            .branch(
-                case![ChatState::LinkReceiveAddress { username }].endpoint(link_receive_address),
+            .await?;
+            Ok(None)
+        }
+        accounts => {
+            let usernames = accounts
+                .iter()
+                .map(|a| a.username.as_str())
+                .collect::<Vec<_>>()
+                .join(", ");
+            bot.send_message(
+                msg.chat.id,
+                format!("You've linked more than one account. Please give one of these usernames: {usernames}"),
             )
-            .branch(
-                case![ChatState::LinkReceiveJwt { username, address }].endpoint(link_receive_jwt),
-            ),
+            .await?;
+            Ok(None)
+        }
+    }
+}
+
+async fn is_owner(connections: &Connections, msg: &Message, uid: u64) -> anyhow::Result<bool> {
+    let Some(user) = msg.from() else {
+        return Ok(false);
+    };
+
+    Ok(connections.registry.owner(uid).await? == Some(user.id))
+}
+
+fn is_admin(connections: &Connections, msg: &Message) -> bool {
+    msg.from()
+        .is_some_and(|user| connections.admins.contains(&user.id))
+}
+
+/// Tells the user when a handler fails, in place of the status message it was
+/// showing if there is one, and cleans up status messages it left behind.
+/// Errors that aren't the user's are passed on to be logged.
+async fn report_errors(
+    mut deps: DependencyMap,
+    cont: dptree::Cont<'static, DependencyMap, Result<(), BotError>>,
+) -> ControlFlow<Result<(), BotError>, DependencyMap> {
+    let statuses = StatusMessages::default();
+    deps.insert(statuses.clone());
+    let bot: Arc<Bot> = deps.get();
+    let update: Arc<Update> = deps.get();
+    let connections: Arc<Arc<Connections>> = deps.get();
+
+    let e = match cont(deps).await {
+        ControlFlow::Break(Err(e)) => e,
+        handled => {
+            statuses.clean_up(&bot).await;
+            return handled;
+        }
+    };
+
+    let text = e.user_message(&connections.explorer_url);
+
+    if statuses.fail(&bot, &text).await {
+        // shown in place of the status message
+    } else if let Some(chat) = update.chat() {
+        if let Err(send_error) = bot
+            .send_message(chat.id, text)
+            .parse_mode(ParseMode::Html)
+            .await
+        {
+            log::warn!(
+                "Failed to tell chat {} about an error: {send_error}",
+                chat.id
+            );
+        }
+    }
+
+    if e.is_expected() {
+        log::info!("{e}");
+        ControlFlow::Break(Ok(()))
+    } else {
+        ControlFlow::Break(Err(e))
+    }
+}
+
+fn dispatcher(
+    bot: Bot,
+    connections: Arc<Connections>,
+    storage: Arc<DialogueStorage>,
+) -> Dispatcher<Bot, BotError, DefaultKey> {
+    Dispatcher::builder(bot, handler())
+        .dependencies(deps![connections, storage])
+        .error_handler(LoggingErrorHandler::with_custom_text(
+            "An error has occurred in the dispatcher",
+        ))
+        .enable_ctrlc_handler()
+        .build()
+}
+
+fn handler() -> UpdateHandler<BotError> {
+    dptree::from_fn(report_errors).chain(
+        dialogue::enter::<Update, DialogueStorage, _, _>().branch(
+            Update::filter_message()
+                .branch(
+                    teloxide::filter_command::<BotCommand, _>()
+                        .chain(dptree::inspect(|command: BotCommand| {
+                            metrics::command(command.name())
+                        }))
+                        .branch(
+                            case![ChatState::Start]
+                                .branch(case![BotCommand::Help].endpoint(help))
+                                .branch(case![BotCommand::Cancel].endpoint(cancel))
+                                .branch(case![BotCommand::Link].endpoint(begin_link))
+                                .branch(case![BotCommand::LinkWallet].endpoint(begin_link_wallet))
+                                .branch(case![BotCommand::Register { username }].endpoint(register))
+                                .branch(case![BotCommand::Update { username }].endpoint(update))
+                                .branch(case![BotCommand::Check { username }].endpoint(check))
+                                .branch(case![BotCommand::Reconcile { fix }].endpoint(reconcile))
+                                .branch(
+                                    case![BotCommand::Unregister { username }].endpoint(unregister),
+                                ),
+                        ),
+                )
+                .branch(
+                    case![ChatState::LinkReceiveUsername { proof }].endpoint(link_receive_username),
+                )
+                .branch(
+                    case![ChatState::LinkReceiveAddress { username, proof }]
+                        .endpoint(link_receive_address),
+                )
+                .branch(
+                    case![ChatState::LinkReceiveSignature {
+                        username,
+                        address,
+                        message
+                    }]
+                    .endpoint(link_receive_signature),
+                )
+                .branch(
+                    case![ChatState::LinkAwaitChallenge {
+                        username,
+                        address,
+                        code,
+                        proof
+                    }]
+                    .endpoint(link_await_challenge),
+                ),
+        ),
     )
 }
 
@@ -316,46 +768,47 @@ async fn check(
     bot: Bot,
     msg: Message,
     connections: Arc<Connections>,
-    username: String,
-) -> anyhow::Result<()> {
-    let bot = bot.parse_mode(ParseMode::Html);
+    statuses: StatusMessages,
+    username: Option<String>,
+) -> Result<(), BotError> {
+    let Some(target) = resolve_target(&bot, &msg, &connections, username).await? else {
+        return Ok(());
+    };
 
-    let loading_msg = bot
-        .send_message(msg.chat.id, "Okay, loading your Duolingo profile...")
+    let status = statuses
+        .show(&bot, msg.chat.id, "Okay, loading your Duolingo profile...")
         .await?;
 
-    let Some((uid, address_in_profile)) =
-        get_user_uid_and_address(&connections.http, &username).await
-    else {
-        bot.delete_message(msg.chat.id, loading_msg.id).await?;
-        bot.send_message(msg.chat.id, "User not found").await?;
-        return Ok(());
-    };
+    let (uid, address_in_profile) = target.find(&connections).await?;
+    let address_in_profile = address_in_profile.ok_or_else(no_address)?;
 
-    let total_xp = get_user_total_xp(&connections.http, uid).await?;
+    let total_xp = connections.duolingo.get_user_total_xp(uid).await?;
 
     let (address_in_contract, xp_in_contract): (Address, U256) =
         connections.contract.users(uid.into()).await?;
 
-    let xp_to_mint = total_xp - xp_in_contract.as_u64();
+    let progress = match XpProgress::new(total_xp, xp_in_contract) {
+        XpProgress::Gained(xp) => format!("you can mint {xp} XP as POD."),
+        XpProgress::Unchanged => "you don't have any new XP to mint.".to_string(),
+        XpProgress::Decreased(xp) => {
+            format!("your XP has dropped {xp} below what was last reported. {HELD_BACK_MESSAGE}")
+        }
+    };
+
+    let mut summary = format!(
+        "Your account has registered the address <code>{}</code>, and {progress}",
+        ethers::utils::to_checksum(&address_in_contract, None)
+    );
 
     if address_in_contract != address_in_profile {
-        bot.send_message(msg.chat.id, format!(
-            "It looks like your address has changed. You've registered to withdraw to <code>{}</code>, but your Duolingo profile has <code>{}</code>.",
+        summary = format!(
+            "It looks like your address has changed. You've registered to withdraw to <code>{}</code>, but your Duolingo profile has <code>{}</code>.\n\n{summary}",
             ethers::utils::to_checksum(&address_in_contract, None),
             ethers::utils::to_checksum(&address_in_profile, None),
-        )).await?;
+        );
     }
 
-    bot.send_message(
-        msg.chat.id,
-        format!(
-            "Your account has registered the address <code>{}</code>, and you can mint {xp_to_mint} XP as POD.",
-            ethers::utils::to_checksum(&address_in_contract, None)
-        ),
-    )
-    .await?;
-    bot.delete_message(msg.chat.id, loading_msg.id).await?;
+    status.finish(summary).await?;
 
     Ok(())
 }
@@ -364,24 +817,25 @@ async fn update(
     bot: Bot,
     msg: Message,
     connections: Arc<Connections>,
-    username: String,
-) -> anyhow::Result<()> {
-    let loading_msg = bot
-        .send_message(msg.chat.id, "Okay, loading your Duolingo profile...")
+    statuses: StatusMessages,
+    username: Option<String>,
+) -> Result<(), BotError> {
+    let Some(target) = resolve_target(&bot, &msg, &connections, username).await? else {
+        return Ok(());
+    };
+
+    let mut status = statuses
+        .show(&bot, msg.chat.id, "Okay, loading your Duolingo profile...")
         .await?;
 
-    let (uid, _address) = get_user_uid_and_address(&connections.http, &username)
-        .await
-        .ok_or_else(|| anyhow::anyhow!("User not found"))?;
+    let (uid, _address) = target.find(&connections).await?;
 
-    let total_xp = get_user_total_xp(&connections.http, uid).await?;
+    let total_xp = connections.duolingo.get_user_total_xp(uid).await?;
 
-    bot.send_message(msg.chat.id, format!("Wow, you have {total_xp} XP!"))
-        .await?;
-    bot.delete_message(msg.chat.id, loading_msg.id).await?;
-
-    let sending_msg = bot
-        .send_message(msg.chat.id, "Minting your rewards...")
+    status
+        .set(format!(
+            "Wow, you have {total_xp} XP! Minting your rewards..."
+        ))
         .await?;
 
     let (_address_in_contract, xp_in_contract): (Address, U256) =
@@ -389,28 +843,43 @@ async fn update(
 
     log::log!(Level::Info, "XP in contract: {}", xp_in_contract.as_u128());
 
-    if xp_in_contract == total_xp.into() {
-        bot.send_message(msg.chat.id, "You need to earn more XP to receive rewards.")
-            .await?;
-        bot.delete_message(msg.chat.id, sending_msg.id).await?;
-        return Ok(());
-    }
-
-    connections
-        .contract
-        .report_xp(uid.into(), total_xp.into())
-        .send()
-        .await?;
+    let gained = match XpProgress::new(total_xp, xp_in_contract) {
+        XpProgress::Gained(xp) => xp,
+        XpProgress::Unchanged => {
+            status
+                .finish(format!(
+                    "You have {total_xp} XP. You need to earn more XP to receive rewards."
+                ))
+                .await?;
+            return Ok(());
+        }
+        XpProgress::Decreased(xp) => {
+            log::warn!("XP for {uid} dropped by {xp} since it was last reported");
+            status
+                .finish(format!(
+                    "Your Duolingo XP is {xp} lower than when it was last reported. {HELD_BACK_MESSAGE}"
+                ))
+                .await?;
+            return Ok(());
+        }
+    };
 
-    bot.send_message(
-        msg.chat.id,
-        format!(
-            "Congratulations, you received {} POD!",
-            (U256::from(total_xp) - xp_in_contract).as_u64()
-        ),
-    )
-    .await?;
-    bot.delete_message(msg.chat.id, sending_msg.id).await?;
+    // read back from the receipt, since another report for the same UID may
+    // have been batched into the same transaction
+    match connections.report_xp(uid, total_xp).await? {
+        TxOutcome::Confirmed(receipt) => {
+            let pod = minted_pod(&receipt, uid, total_xp).unwrap_or(gained.into());
+            status
+                .finish(format!(
+                    "Congratulations, you received {pod} POD! {}",
+                    tx_link(&connections.explorer_url, receipt.transaction_hash),
+                ))
+                .await?;
+        }
+        TxOutcome::Reverted { reason, tx_hash } => {
+            return Err(BotError::Reverted { reason, tx_hash });
+        }
+    }
 
     Ok(())
 }
@@ -419,34 +888,39 @@ async fn unregister(
     bot: Bot,
     msg: Message,
     connections: Arc<Connections>,
-    username: String,
-) -> anyhow::Result<()> {
-    let loading_msg = bot
-        .send_message(msg.chat.id, "Okay, loading your Duolingo profile...")
+    statuses: StatusMessages,
+    username: Option<String>,
+) -> Result<(), BotError> {
+    let Some(target) = resolve_target(&bot, &msg, &connections, username).await? else {
+        return Ok(());
+    };
+
+    let mut status = statuses
+        .show(&bot, msg.chat.id, "Okay, loading your Duolingo profile...")
         .await?;
 
-    let (uid, _address) = get_user_uid_and_address(&connections.http, &username)
-        .await
-        .ok_or_else(|| anyhow::anyhow!("User not found"))?;
+    let (uid, _address) = target.find(&connections).await?;
 
-    let unregistering_msg = bot
-        .send_message(msg.chat.id, "Unregistering you from the contract...")
-        .await?;
-    bot.delete_message(msg.chat.id, loading_msg.id).await?;
+    if !is_owner(&connections, &msg, uid).await? {
+        status.finish(NOT_OWNER_MESSAGE).await?;
+        return Ok(());
+    }
 
-    connections
-        .contract
-        .user_unregister(uid.into())
-        .send()
-        .await?;
+    status.set("Unregistering you from the contract...").await?;
 
-    bot.send_message(
-        msg.chat.id,
-        "You've been unregistered. Sorry to see you go!",
-    )
-    .await?;
-    bot.delete_message(msg.chat.id, unregistering_msg.id)
-        .await?;
+    match connections.unregister(uid).await? {
+        TxOutcome::Confirmed(receipt) => {
+            status
+                .finish(format!(
+                    "You've been unregistered. Sorry to see you go! {}",
+                    tx_link(&connections.explorer_url, receipt.transaction_hash),
+                ))
+                .await?;
+        }
+        TxOutcome::Reverted { reason, tx_hash } => {
+            return Err(BotError::Reverted { reason, tx_hash });
+        }
+    }
 
     Ok(())
 }
@@ -455,87 +929,172 @@ async fn register(
     bot: Bot,
     msg: Message,
     connections: Arc<Connections>,
-    username: String,
-) -> anyhow::Result<()> {
-    let bot = bot.parse_mode(ParseMode::Html);
+    statuses: StatusMessages,
+    username: Option<String>,
+) -> Result<(), BotError> {
+    let Some(target) = resolve_target(&bot, &msg, &connections, username).await? else {
+        return Ok(());
+    };
 
-    let loading_msg = bot
-        .send_message(msg.chat.id, "Okay, loading your Duolingo profile...")
+    let mut status = statuses
+        .show(&bot, msg.chat.id, "Okay, loading your Duolingo profile...")
         .await?;
 
-    let (uid, address) = get_user_uid_and_address(&connections.http, &username)
-        .await
-        .ok_or_else(|| anyhow::anyhow!("User not found"))?;
+    let (uid, address) = target.find(&connections).await?;
+    let address = address.ok_or_else(no_address)?;
 
-    let checking_registration_msg = bot
-        .send_message(msg.chat.id, "Found you! Checking your registration...")
+    if !is_owner(&connections, &msg, uid).await? {
+        status.finish(NOT_OWNER_MESSAGE).await?;
+        return Ok(());
+    }
+
+    status
+        .set("Found you! Checking your registration...")
         .await?;
-    bot.delete_message(msg.chat.id, loading_msg.id).await?;
 
     let ((address_from_contract, _xp_from_contract), xp_from_duolingo) = tokio::try_join!(
         async {
             let r: (Address, U256) = connections.contract.users(uid.into()).await?;
             Ok(r)
         },
-        async { get_user_total_xp(&connections.http, uid).await },
+        async { connections.duolingo.get_user_total_xp(uid).await },
     )?;
 
     if address_from_contract.is_zero() {
-        let registration_msg = bot
-            .send_message(
-                msg.chat.id,
-                format!(
-                    "Registering <code>{}</code> with the contract...",
-                    ethers::utils::to_checksum(&address, None)
-                ),
-            )
-            .await?;
-        bot.delete_message(msg.chat.id, checking_registration_msg.id)
+        status
+            .set(format!(
+                "Registering <code>{}</code> with the contract...",
+                ethers::utils::to_checksum(&address, None)
+            ))
             .await?;
 
-        connections
-            .contract
-            .user_register(uid.into(), address, xp_from_duolingo.into())
-            .send()
-            .await?;
-
-        bot.send_message(msg.chat.id, "Registered!").await?;
-        bot.delete_message(msg.chat.id, registration_msg.id).await?;
+        match connections.register(uid, address, xp_from_duolingo).await? {
+            TxOutcome::Confirmed(receipt) => {
+                status
+                    .finish(format!(
+                        "Registered! {}",
+                        tx_link(&connections.explorer_url, receipt.transaction_hash),
+                    ))
+                    .await?;
+            }
+            TxOutcome::Reverted { reason, tx_hash } => {
+                return Err(BotError::Reverted { reason, tx_hash });
+            }
+        }
     } else if address_from_contract != address {
-        let update_msg = bot
-            .send_message(msg.chat.id, "Looks like we need to update your profile...")
-            .await?;
-        bot.delete_message(msg.chat.id, checking_registration_msg.id)
+        status
+            .set("Looks like we need to update your profile...")
             .await?;
 
-        connections
-            .contract
-            .user_update_address(uid.into(), address)
-            .send()
-            .await?;
-
-        bot.delete_message(msg.chat.id, update_msg.id).await?;
-        bot.send_message(msg.chat.id, "Updated!").await?;
+        match connections.update_address(uid, address).await? {
+            TxOutcome::Confirmed(receipt) => {
+                status
+                    .finish(format!(
+                        "Updated! {}",
+                        tx_link(&connections.explorer_url, receipt.transaction_hash),
+                    ))
+                    .await?;
+            }
+            TxOutcome::Reverted { reason, tx_hash } => {
+                return Err(BotError::Reverted { reason, tx_hash });
+            }
+        }
     } else {
-        bot.send_message(msg.chat.id, "Already registered!").await?;
-        bot.delete_message(msg.chat.id, checking_registration_msg.id)
-            .await?;
+        status.finish("Already registered!").await?;
     }
 
     Ok(())
 }
 
-async fn begin_link(
+/// Works from the event index, so `run` has to be indexing.
+async fn reconcile(
     bot: Bot,
     msg: Message,
-    dialogue: Dialogue<ChatState, InMemStorage<ChatState>>,
-) -> anyhow::Result<()> {
+    connections: Arc<Connections>,
+    statuses: StatusMessages,
+    fix: bool,
+) -> Result<(), BotError> {
+    // Telegram cuts messages off at 4096 characters
+    const MAX_REPORT_LEN: usize = 3500;
+
+    if !is_admin(&connections, &msg) {
+        return Err(BotError::Validation(
+            "Only the bot's admins can do that.".to_string(),
+        ));
+    }
+
+    let status = statuses
+        .show(
+            &bot,
+            msg.chat.id,
+            "Comparing the contract with Duolingo and the registry...",
+        )
+        .await?;
+
+    if connections
+        .registry
+        .index_cursor(connections.contract_address)
+        .await?
+        .is_none()
+    {
+        return Err(BotError::Validation(
+            "Nothing has been indexed yet, so I don't know who is registered. \
+             Set --index-from-block to start indexing."
+                .to_string(),
+        ));
+    }
+
+    let report = reconcile::reconcile(&connections, fix, reconcile::DEFAULT_XP_THRESHOLD).await?;
+
+    let report = report.to_string();
+    let mut text = String::new();
+    let mut left = report.lines().count();
+    for line in report.lines() {
+        if text.len() + line.len() >= MAX_REPORT_LEN {
+            text.push_str(&format!(
+                "...and {left} more. Run `reconcile` from the command line for the full report.\n"
+            ));
+            break;
+        }
+
+        text.push_str(line);
+        text.push('\n');
+        left -= 1;
+    }
+
+    status
+        .finish(format!(
+            "<pre>{}</pre>",
+            teloxide::utils::html::escape(&text)
+        ))
+        .await?;
+
+    Ok(())
+}
+
+async fn begin_link(bot: Bot, msg: Message, dialogue: ChatDialogue) -> Result<(), BotError> {
+    start_link(bot, msg, dialogue, AddressProof::Bio).await
+}
+
+async fn begin_link_wallet(bot: Bot, msg: Message, dialogue: ChatDialogue) -> Result<(), BotError> {
+    start_link(bot, msg, dialogue, AddressProof::Signature).await
+}
+
+async fn start_link(
+    bot: Bot,
+    msg: Message,
+    dialogue: ChatDialogue,
+    proof: AddressProof,
+) -> Result<(), BotError> {
     bot.send_message(msg.chat.id, "Let's get your Duolingo account set up.")
         .await?;
     bot.send_message(msg.chat.id, "First, what's your username?")
         .await?;
 
-    dialogue.update(ChatState::LinkReceiveUsername).await?;
+    dialogue
+        .update(ChatState::LinkReceiveUsername { proof })
+        .await
+        .map_err(|e| anyhow::anyhow!(e))?;
 
     Ok(())
 }
@@ -543,18 +1102,19 @@ async fn begin_link(
 async fn link_receive_username(
     bot: Bot,
     msg: Message,
-    dialogue: Dialogue<ChatState, InMemStorage<ChatState>>,
+    dialogue: ChatDialogue,
     connections: Arc<Connections>,
-) -> anyhow::Result<()> {
+    proof: AddressProof,
+) -> Result<(), BotError> {
     let bot = bot.parse_mode(ParseMode::Html);
 
     if let Some(text) = msg.text() {
-        let found_user = get_user_uid_and_maybe_address(&connections.http, text).await;
+        let found_user = get_user_uid_and_maybe_address(&*connections.duolingo, text).await?;
         if let Some((_uid, address)) = found_user {
             bot.send_message(msg.chat.id, "Great to meet you!").await?;
             bot.send_message(msg.chat.id, "Now, we need to link your profile.")
                 .await?;
-            if let Some(address) = address {
+            if let (Some(address), AddressProof::Bio) = (address, proof) {
                 bot.send_message(
                     msg.chat.id,
                     format!(
@@ -568,8 +1128,10 @@ async fn link_receive_username(
             dialogue
                 .update(ChatState::LinkReceiveAddress {
                     username: text.to_owned(),
+                    proof,
                 })
-                .await?;
+                .await
+                .map_err(|e| anyhow::anyhow!(e))?;
 
             bot.send_message(msg.chat.id, "What is your Taiko address?")
                 .await?;
@@ -588,19 +1150,65 @@ async fn link_receive_username(
 async fn link_receive_address(
     bot: Bot,
     msg: Message,
-    dialogue: Dialogue<ChatState, InMemStorage<ChatState>>,
-    username: String,
-) -> anyhow::Result<()> {
+    dialogue: ChatDialogue,
+    connections: Arc<Connections>,
+    (username, proof): (String, AddressProof),
+) -> Result<(), BotError> {
     if let Some(address) = msg.text() {
         let address = ethers::utils::parse_checksummed(address, None);
 
         if let Ok(address) = address {
-            dialogue
-                .update(ChatState::LinkReceiveJwt { username, address })
-                .await?;
-
-            bot.send_message(msg.chat.id, "Okay, now please send your JWT. You can find instructions for how to get it here: https://github.com/encody/duopow")
-                .await?;
+            match proof {
+                AddressProof::Bio => {
+                    let Some(user) = msg.from() else {
+                        bot.send_message(
+                            msg.chat.id,
+                            "Please link your profile from a private chat.",
+                        )
+                        .await?;
+                        return Ok(());
+                    };
+
+                    let challenge = Challenge::new(username, address, proof);
+                    bot.clone()
+                        .parse_mode(ParseMode::Html)
+                        .send_message(
+                            msg.chat.id,
+                            format!(
+                                "Now, to show the Duolingo account is yours, put <code>{} {}</code> anywhere in your Duolingo bio. I'll keep checking for the next {} minutes, or send me any message to check right away.\n\nIf you'd rather I add the address for you, please send your JWT instead. You can find instructions for how to get it here: https://github.com/encody/duopow",
+                                ethers::utils::to_checksum(&address, None),
+                                challenge.code,
+                                CHALLENGE_TIMEOUT.as_secs() / 60,
+                            ),
+                        )
+                        .await?;
+
+                    start_challenge(bot, dialogue, connections, user.id, challenge).await?;
+                }
+                AddressProof::Signature => {
+                    let message = link_message(&username, address, msg.chat.id);
+
+                    bot.parse_mode(ParseMode::Html)
+                        .send_message(
+                            msg.chat.id,
+                            format!(
+                                "Please sign this message with the wallet for <code>{}</code> (personal_sign), and send the signature here:\n\n<pre>{}</pre>",
+                                ethers::utils::to_checksum(&address, None),
+                                teloxide::utils::html::escape(&message),
+                            ),
+                        )
+                        .await?;
+
+                    dialogue
+                        .update(ChatState::LinkReceiveSignature {
+                            username,
+                            address,
+                            message,
+                        })
+                        .await
+                        .map_err(|e| anyhow::anyhow!(e))?;
+                }
+            }
         } else {
             bot.send_message(msg.chat.id, "Invalid address. Please try again.")
                 .await?;
@@ -613,27 +1221,298 @@ async fn link_receive_address(
     Ok(())
 }
 
-async fn link_receive_jwt(
+/// The message a user signs to prove they control `address`. The nonce stops
+/// an old signature from being replayed.
+fn link_message(username: &str, address: Address, chat_id: ChatId) -> String {
+    let nonce = ethers::utils::hex::encode(ethers::core::rand::random::<[u8; 16]>());
+
+    format!(
+        "Link Duolingo account {username} to {} in Telegram chat {chat_id} on DuoPow.\n\nNonce: {nonce}",
+        ethers::utils::to_checksum(&address, None),
+    )
+}
+
+async fn link_receive_signature(
     bot: Bot,
     msg: Message,
-    dialogue: Dialogue<ChatState, InMemStorage<ChatState>>,
+    dialogue: ChatDialogue,
+    connections: Arc<Connections>,
+    (username, address, message): (String, Address, String),
+) -> Result<(), BotError> {
+    let Some(text) = msg.text() else {
+        bot.send_message(msg.chat.id, "Please send a signature.")
+            .await?;
+        return Ok(());
+    };
+
+    let verified = text
+        .trim()
+        .parse::<ethers::types::Signature>()
+        .is_ok_and(|signature| signature.verify(message.as_str(), address).is_ok());
+
+    if !verified {
+        bot.send_message(
+            msg.chat.id,
+            "That signature doesn't match your address. Please try again.",
+        )
+        .await?;
+        return Ok(());
+    }
+
+    let Some(user) = msg.from() else {
+        bot.send_message(msg.chat.id, "Please link your profile from a private chat.")
+            .await?;
+        return Ok(());
+    };
+
+    let challenge = Challenge::new(username, address, AddressProof::Signature);
+    bot.clone()
+        .parse_mode(ParseMode::Html)
+        .send_message(
+            msg.chat.id,
+            format!(
+                "Signature verified! Now, to show the Duolingo account is yours, add <code>{}</code> anywhere in your Duolingo bio. I'll keep checking for the next {} minutes, or send me any message to check right away.",
+                challenge.code,
+                CHALLENGE_TIMEOUT.as_secs() / 60,
+            ),
+        )
+        .await?;
+
+    start_challenge(bot, dialogue, connections, user.id, challenge).await?;
+
+    Ok(())
+}
+
+const CHALLENGE_POLL_INTERVAL: Duration = Duration::from_secs(15);
+const CHALLENGE_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Clone)]
+struct Challenge {
+    username: String,
+    address: Address,
+    code: String,
+    proof: AddressProof,
+}
+
+impl Challenge {
+    fn new(username: String, address: Address, proof: AddressProof) -> Self {
+        let code = format!(
+            "duopow-{}",
+            ethers::utils::hex::encode(ethers::core::rand::random::<[u8; 4]>())
+        );
+
+        Self {
+            username,
+            address,
+            code,
+            proof,
+        }
+    }
+}
+
+/// Waits for the challenge code in the chat's dialogue, and starts checking
+/// the user's bio for it in the background.
+async fn start_challenge(
+    bot: Bot,
+    dialogue: ChatDialogue,
     connections: Arc<Connections>,
-    (_username, address): (String, Address),
+    user_id: UserId,
+    challenge: Challenge,
 ) -> anyhow::Result<()> {
-    if let Some(jwt) = msg.text() {
-        bot.send_message(msg.chat.id, "Got it! Linking profile...")
+    dialogue
+        .update(ChatState::LinkAwaitChallenge {
+            username: challenge.username.clone(),
+            address: challenge.address,
+            code: challenge.code.clone(),
+            proof: challenge.proof,
+        })
+        .await
+        .map_err(|e| anyhow::anyhow!(e))?;
+
+    tokio::spawn(poll_challenge(
+        bot,
+        dialogue,
+        connections,
+        user_id,
+        challenge,
+    ));
+
+    Ok(())
+}
+
+async fn poll_challenge(
+    bot: Bot,
+    dialogue: ChatDialogue,
+    connections: Arc<Connections>,
+    user_id: UserId,
+    challenge: Challenge,
+) {
+    let deadline = tokio::time::Instant::now() + CHALLENGE_TIMEOUT;
+
+    while tokio::time::Instant::now() < deadline {
+        tokio::time::sleep(CHALLENGE_POLL_INTERVAL).await;
+
+        match try_complete_challenge(&bot, &dialogue, &connections, user_id, &challenge).await {
+            Ok(true) => return,
+            Ok(false) => {}
+            Err(e) => log::warn!("Failed to check the link challenge: {e:#}"),
+        }
+    }
+}
+
+async fn link_await_challenge(
+    bot: Bot,
+    msg: Message,
+    dialogue: ChatDialogue,
+    connections: Arc<Connections>,
+    (username, address, code, proof): (String, Address, String, AddressProof),
+) -> Result<(), BotError> {
+    let Some(user) = msg.from() else {
+        return Ok(());
+    };
+
+    if let (AddressProof::Bio, Some(text)) = (proof, msg.text()) {
+        if !matches!(jwt::parse(text), Err(jwt::JwtError::Malformed)) {
+            return link_with_jwt(
+                &bot,
+                &msg,
+                &dialogue,
+                &connections,
+                &username,
+                address,
+                text,
+            )
+            .await;
+        }
+    }
+
+    let challenge = Challenge {
+        username,
+        address,
+        code,
+        proof,
+    };
+
+    if !try_complete_challenge(&bot, &dialogue, &connections, user.id, &challenge).await? {
+        let expected = match challenge.proof {
+            AddressProof::Bio => format!(
+                "{} {}",
+                ethers::utils::to_checksum(&challenge.address, None),
+                challenge.code
+            ),
+            AddressProof::Signature => challenge.code,
+        };
+        bot.parse_mode(ParseMode::Html)
+            .send_message(
+                msg.chat.id,
+                format!(
+                    "I can't see <code>{expected}</code> in your Duolingo bio yet. It can take a minute for changes to show up."
+                ),
+            )
             .await?;
-        bot.delete_message(msg.chat.id, msg.id).await?;
-        add_address_to_profile(&connections.http, jwt, address).await?;
-        dialogue.update(ChatState::Start).await?;
-        bot.send_message(msg.chat.id, "Profile linked!").await?;
-    } else {
-        bot.send_message(msg.chat.id, "Please send a JWT.").await?;
     }
 
     Ok(())
 }
 
+/// Links the account if the challenge code is in the user's bio and the chat
+/// is still waiting on this challenge. Returns whether it was linked.
+async fn try_complete_challenge(
+    bot: &Bot,
+    dialogue: &ChatDialogue,
+    connections: &Connections,
+    user_id: UserId,
+    challenge: &Challenge,
+) -> anyhow::Result<bool> {
+    let waiting = matches!(
+        dialogue.get().await.map_err(|e| anyhow::anyhow!(e))?,
+        Some(ChatState::LinkAwaitChallenge { code, .. }) if code == challenge.code
+    );
+    if !waiting {
+        // cancelled, or already linked by another check
+        return Ok(true);
+    }
+
+    let user = connections
+        .duolingo
+        .get_user_by_username(&challenge.username)
+        .await?
+        .ok_or_else(|| BotError::UserNotFound(challenge.username.clone()))?;
+    if !user.bio.contains(&challenge.code) {
+        return Ok(false);
+    }
+    // without a signature, the bio is what the address is trusted from later
+    if challenge.proof == AddressProof::Bio && address_in_bio(&user.bio) != Some(challenge.address)
+    {
+        return Ok(false);
+    }
+
+    connections
+        .registry
+        .link(user_id, user.id, &challenge.username)
+        .await?;
+    if challenge.proof == AddressProof::Signature {
+        connections
+            .registry
+            .record_verified_address(user.id, challenge.address)
+            .await?;
+    }
+    dialogue
+        .update(ChatState::Start)
+        .await
+        .map_err(|e| anyhow::anyhow!(e))?;
+
+    bot.send_message(
+        dialogue.chat_id(),
+        "Profile linked! You can take the code out of your bio now.",
+    )
+    .await?;
+
+    Ok(true)
+}
+
+/// Links by writing the address into the user's bio with their JWT.
+async fn link_with_jwt(
+    bot: &Bot,
+    msg: &Message,
+    dialogue: &ChatDialogue,
+    connections: &Connections,
+    username: &str,
+    address: Address,
+    jwt: &str,
+) -> Result<(), BotError> {
+    let Some(user) = msg.from() else {
+        bot.send_message(msg.chat.id, "Please link your profile from a private chat.")
+            .await?;
+        return Ok(());
+    };
+
+    // don't leave credentials lying around in the chat, even invalid ones
+    bot.delete_message(msg.chat.id, msg.id).await?;
+
+    let (uid, _address) = get_user_uid_and_maybe_address(&*connections.duolingo, username)
+        .await?
+        .ok_or_else(|| BotError::UserNotFound(username.to_owned()))?;
+
+    if let Err(e) = jwt::parse(jwt).and_then(|claims| claims.expect_sub(uid)) {
+        bot.send_message(msg.chat.id, format!("{e}. Please try again."))
+            .await?;
+        return Ok(());
+    }
+
+    bot.send_message(msg.chat.id, "Got it! Linking profile...")
+        .await?;
+    add_address_to_profile(&*connections.duolingo, uid, jwt, address).await?;
+    connections.registry.link(user.id, uid, username).await?;
+    dialogue
+        .update(ChatState::Start)
+        .await
+        .map_err(|e| anyhow::anyhow!(e))?;
+    bot.send_message(msg.chat.id, "Profile linked!").await?;
+
+    Ok(())
+}
+
 #[tokio::test]
 async fn test_rpc() {
     let rpc = ethers::providers::Provider::try_from("https://rpc.mainnet.taiko.xyz/").unwrap();
@@ -652,18 +1531,17 @@ async fn test_rpc() {
     println!("{b:?}");
 }
 
-async fn cancel(
-    bot: Bot,
-    dialogue: Dialogue<ChatState, InMemStorage<ChatState>>,
-    msg: Message,
-) -> anyhow::Result<()> {
+async fn cancel(bot: Bot, dialogue: ChatDialogue, msg: Message) -> Result<(), BotError> {
     bot.send_message(msg.chat.id, "Cancelling.").await?;
 
-    dialogue.update(ChatState::Start).await?;
+    dialogue
+        .update(ChatState::Start)
+        .await
+        .map_err(|e| anyhow::anyhow!(e))?;
     Ok(())
 }
 
-async fn help(bot: Bot, msg: Message, connections: Arc<Connections>) -> anyhow::Result<()> {
+async fn help(bot: Bot, msg: Message, connections: Arc<Connections>) -> Result<(), BotError> {
     bot.send_message(msg.chat.id, BotCommand::descriptions().to_string())
         .await?;
 
@@ -681,77 +1559,10 @@ async fn help(bot: Bot, msg: Message, connections: Arc<Connections>) -> anyhow::
     Ok(())
 }
 
-async fn get_user_by_uid(
-    http: &reqwest::Client,
-    uid: u64,
-    jwt: &str,
-) -> anyhow::Result<UserResponse> {
-    let response = http
-        .get(format!("https://www.duolingo.com/2017-06-30/users/{uid}"))
-        .header("Host", "www.duolingo.com")
-        .header(
-            "User-Agent",
-            "Mozilla/5.0 (Macintosh; Intel Mac OS X 10.15; rv:127.0) Gecko/20100101 Firefox/127.0",
-        )
-        .bearer_auth(jwt)
-        .send()
-        .await?;
-
-    let user_response = response.json::<UserResponse>().await?;
-
-    Ok(user_response)
-}
-
-fn get_uid_from_jwt(token: &str) -> u64 {
-    #[derive(Deserialize)]
-    struct Sub {
-        sub: u64,
-    }
-
-    let sub = serde_json::from_slice::<Sub>(
-        &base64::Engine::decode(
-            &base64::prelude::BASE64_STANDARD_NO_PAD,
-            token.split('.').nth(1).unwrap(),
-        )
-        .unwrap(),
-    )
-    .unwrap()
-    .sub;
-
-    sub
-}
-
-async fn add_address_to_profile(
-    http: &reqwest::Client,
-    jwt: &str,
-    address: Address,
-) -> anyhow::Result<()> {
-    let uid = get_uid_from_jwt(jwt);
-    let original_bio = get_user_by_uid(http, uid, jwt).await.unwrap().bio;
-    let address_str = ethers::utils::to_checksum(&address, None);
-    let new_bio = if ETH_ADDRESS.is_match(&original_bio) {
-        ETH_ADDRESS.replace(&original_bio, address_str)
-    } else {
-        std::borrow::Cow::Owned(format!("{} {}", original_bio, address_str))
-    };
-
-    // panic!("{}", new_bio);
-
-    // send update
-    http.patch(format!("https://www.duolingo.com/2017-06-30/users/{uid}"))
-        .query(&[("fields", "bio")])
-        .bearer_auth(jwt)
-        .header(
-            "User-Agent",
-            "Mozilla/5.0 (Macintosh; Intel Mac OS X 10.15; rv:127.0) Gecko/20100101 Firefox/127.0",
-        )
-        .header("Referer", "https://www.duolingo.com/settings/profile")
-        .json(&json!({
-            "bio": new_bio,
-        }))
-        .send()
-        .await
-        .unwrap();
-
-    Ok(())
+#[test]
+fn test_xp_progress() {
+    assert_eq!(XpProgress::new(130, 100.into()), XpProgress::Gained(30));
+    assert_eq!(XpProgress::new(100, 100.into()), XpProgress::Unchanged);
+    // used to underflow in `check`
+    assert_eq!(XpProgress::new(80, 100.into()), XpProgress::Decreased(20));
 }
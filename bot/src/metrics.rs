@@ -0,0 +1,209 @@
+use std::{future::Future, net::TcpListener, sync::Arc, time::Duration};
+
+use axum::{extract::State, http::StatusCode, routing::get, Router};
+use ethers::{providers::Middleware, types::U256, utils::format_units};
+use once_cell::sync::Lazy;
+use prometheus::{
+    Counter, Gauge, HistogramOpts, HistogramVec, IntCounterVec, Opts, Registry, TextEncoder,
+};
+use tokio::time::{timeout, Instant};
+
+use crate::{
+    balance,
+    tx::{self, TxOutcome},
+    Connections,
+};
+
+/// How long `/metrics` and `/healthz` wait on the RPC before giving up.
+const RPC_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub static METRICS: Lazy<Metrics> = Lazy::new(Metrics::new);
+
+pub struct Metrics {
+    registry: Registry,
+    commands: IntCounterVec,
+    duolingo_latency: HistogramVec,
+    duolingo_errors: IntCounterVec,
+    transactions: IntCounterVec,
+    pod_minted: Counter,
+    owner_balance: Gauge,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new();
+
+        let commands = IntCounterVec::new(
+            Opts::new("duopow_commands_total", "Bot commands received"),
+            &["command"],
+        )
+        .unwrap();
+        let duolingo_latency = HistogramVec::new(
+            HistogramOpts::new(
+                "duopow_duolingo_request_duration_seconds",
+                "Duolingo API request latency",
+            ),
+            &["endpoint"],
+        )
+        .unwrap();
+        let duolingo_errors = IntCounterVec::new(
+            Opts::new(
+                "duopow_duolingo_errors_total",
+                "Failed Duolingo API requests",
+            ),
+            &["endpoint"],
+        )
+        .unwrap();
+        let transactions = IntCounterVec::new(
+            Opts::new(
+                "duopow_transactions_total",
+                "Owner transactions by outcome: sent, confirmed or reverted",
+            ),
+            &["outcome"],
+        )
+        .unwrap();
+        let pod_minted = Counter::new("duopow_pod_minted_total", "POD minted by the bot").unwrap();
+        let owner_balance = Gauge::new(
+            "duopow_owner_balance_ether",
+            "Balance of the owner wallet, updated on every scrape and balance check",
+        )
+        .unwrap();
+
+        registry.register(Box::new(commands.clone())).unwrap();
+        registry
+            .register(Box::new(duolingo_latency.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(duolingo_errors.clone()))
+            .unwrap();
+        registry.register(Box::new(transactions.clone())).unwrap();
+        registry.register(Box::new(pod_minted.clone())).unwrap();
+        registry.register(Box::new(owner_balance.clone())).unwrap();
+
+        Self {
+            registry,
+            commands,
+            duolingo_latency,
+            duolingo_errors,
+            transactions,
+            pod_minted,
+            owner_balance,
+        }
+    }
+
+    /// Counts a transaction's outcome, and the POD it minted if it went
+    /// through. `DuolingoPow` mints through `mintTo`, which only emits a
+    /// `Transfer` from the zero address.
+    pub fn tx_settled(&self, outcome: &TxOutcome) {
+        match outcome {
+            TxOutcome::Confirmed(receipt) => {
+                self.transactions.with_label_values(&["confirmed"]).inc();
+
+                let minted = receipt
+                    .logs
+                    .iter()
+                    .filter_map(tx::minted)
+                    .fold(U256::zero(), |total, amount| total + amount);
+                self.pod_minted.inc_by(ether(minted));
+            }
+            TxOutcome::Reverted { .. } => {
+                self.transactions.with_label_values(&["reverted"]).inc();
+            }
+        }
+    }
+
+    #[cfg(test)]
+    pub fn pod_minted(&self) -> f64 {
+        self.pod_minted.get()
+    }
+}
+
+fn ether(amount: U256) -> f64 {
+    format_units(amount, "ether")
+        .ok()
+        .and_then(|amount| amount.parse().ok())
+        .unwrap_or(f64::NAN)
+}
+
+pub fn command(name: &str) {
+    METRICS.commands.with_label_values(&[name]).inc();
+}
+
+/// Records how long a Duolingo API call took and whether it failed.
+pub async fn time_duolingo<T>(
+    endpoint: &str,
+    request: impl Future<Output = anyhow::Result<T>>,
+) -> anyhow::Result<T> {
+    let start = Instant::now();
+    let result = request.await;
+
+    METRICS
+        .duolingo_latency
+        .with_label_values(&[endpoint])
+        .observe(start.elapsed().as_secs_f64());
+    if result.is_err() {
+        METRICS.duolingo_errors.with_label_values(&[endpoint]).inc();
+    }
+
+    result
+}
+
+pub fn owner_balance(balance: U256) {
+    METRICS.owner_balance.set(ether(balance));
+}
+
+pub fn tx_sent() {
+    METRICS.transactions.with_label_values(&["sent"]).inc();
+}
+
+pub fn tx_settled(outcome: &TxOutcome) {
+    METRICS.tx_settled(outcome);
+}
+
+/// Serves `/metrics` for Prometheus and `/healthz`, which checks that the RPC
+/// is reachable.
+pub fn serve(listener: TcpListener, connections: Arc<Connections>) -> anyhow::Result<()> {
+    listener.set_nonblocking(true)?;
+    let server = axum::Server::from_tcp(listener)?.serve(
+        Router::new()
+            .route("/metrics", get(metrics))
+            .route("/healthz", get(healthz))
+            .with_state(connections)
+            .into_make_service(),
+    );
+    tokio::spawn(async move {
+        if let Err(e) = server.await {
+            log::error!("Metrics server stopped: {e}");
+        }
+    });
+
+    Ok(())
+}
+
+async fn metrics(
+    State(connections): State<Arc<Connections>>,
+) -> Result<String, (StatusCode, String)> {
+    match timeout(RPC_TIMEOUT, balance::owner_balance(&connections.contract)).await {
+        Ok(Ok(balance)) => owner_balance(balance),
+        Ok(Err(e)) => log::warn!("Failed to fetch the owner balance: {e:#}"),
+        Err(_) => log::warn!("Timed out fetching the owner balance"),
+    }
+
+    TextEncoder::new()
+        .encode_to_string(&METRICS.registry.gather())
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
+
+async fn healthz(State(connections): State<Arc<Connections>>) -> (StatusCode, String) {
+    match timeout(RPC_TIMEOUT, connections.contract.client().get_chainid()).await {
+        Ok(Ok(chain_id)) => (StatusCode::OK, format!("ok, chain {chain_id}\n")),
+        Ok(Err(e)) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            format!("RPC unreachable: {e}\n"),
+        ),
+        Err(_) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "RPC timed out\n".to_string(),
+        ),
+    }
+}
@@ -0,0 +1,305 @@
+use std::{
+    collections::HashMap,
+    net::TcpListener,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    routing::get,
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MockUser {
+    pub id: u64,
+    pub username: String,
+    #[serde(default)]
+    pub bio: String,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub streak: u32,
+    #[serde(default)]
+    pub total_xp: u64,
+}
+
+impl MockUser {
+    #[cfg(test)]
+    pub fn new(id: u64, username: &str) -> Self {
+        Self {
+            id,
+            username: username.to_owned(),
+            bio: String::new(),
+            name: username.to_owned(),
+            streak: 0,
+            total_xp: 0,
+        }
+    }
+
+    #[cfg(test)]
+    pub fn with_bio(mut self, bio: &str) -> Self {
+        self.bio = bio.to_owned();
+        self
+    }
+
+    #[cfg(test)]
+    pub fn with_total_xp(mut self, total_xp: u64) -> Self {
+        self.total_xp = total_xp;
+        self
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        json!({
+            "id": self.id,
+            "username": self.username,
+            "bio": self.bio,
+            "name": self.name,
+            "streak": self.streak,
+            "totalXp": self.total_xp,
+            "courses": [],
+        })
+    }
+}
+
+/// In-memory stand-in for the parts of the Duolingo API that the bot uses.
+#[derive(Clone, Default)]
+pub struct MockDuolingo {
+    users: Arc<Mutex<HashMap<u64, MockUser>>>,
+}
+
+impl MockDuolingo {
+    pub fn new(users: impl IntoIterator<Item = MockUser>) -> Self {
+        let mock = Self::default();
+        for user in users {
+            mock.insert_user(user);
+        }
+        mock
+    }
+
+    pub fn insert_user(&self, user: MockUser) {
+        self.users.lock().unwrap().insert(user.id, user);
+    }
+
+    pub fn user(&self, uid: u64) -> Option<MockUser> {
+        self.users.lock().unwrap().get(&uid).cloned()
+    }
+
+    #[cfg(test)]
+    pub fn set_total_xp(&self, uid: u64, total_xp: u64) {
+        if let Some(user) = self.users.lock().unwrap().get_mut(&uid) {
+            user.total_xp = total_xp;
+        }
+    }
+
+    #[cfg(test)]
+    pub fn set_username(&self, uid: u64, username: &str) {
+        if let Some(user) = self.users.lock().unwrap().get_mut(&uid) {
+            user.username = username.to_owned();
+        }
+    }
+
+    #[cfg(test)]
+    pub fn set_bio(&self, uid: u64, bio: &str) {
+        if let Some(user) = self.users.lock().unwrap().get_mut(&uid) {
+            user.bio = bio.to_owned();
+        }
+    }
+
+    pub fn router(&self) -> Router {
+        Router::new()
+            .route("/2017-06-30/users", get(find_users))
+            .route("/2017-06-30/users/:uid", get(get_user).patch(patch_user))
+            .with_state(self.clone())
+    }
+
+    pub async fn serve(self, listener: TcpListener) -> anyhow::Result<()> {
+        listener.set_nonblocking(true)?;
+        axum::Server::from_tcp(listener)?
+            .serve(self.router().into_make_service())
+            .await?;
+        Ok(())
+    }
+
+    /// Serves on an ephemeral local port in the background and returns the
+    /// base URL to hand to `DuolingoClient`.
+    #[cfg(test)]
+    pub fn spawn(self) -> anyhow::Result<reqwest::Url> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let url = reqwest::Url::parse(&format!("http://{}/", listener.local_addr()?))?;
+        tokio::spawn(async move {
+            if let Err(e) = self.serve(listener).await {
+                log::error!("Mock Duolingo server stopped: {e}");
+            }
+        });
+        Ok(url)
+    }
+}
+
+/// Builds an unsigned JWT-shaped token with the given `sub`, which is all the
+/// bot and the mock server look at.
+pub fn jwt_for(sub: u64) -> String {
+    use base64::{prelude::BASE64_URL_SAFE_NO_PAD, Engine};
+
+    format!(
+        "{}.{}.mock",
+        BASE64_URL_SAFE_NO_PAD.encode(json!({ "alg": "HS256", "typ": "JWT" }).to_string()),
+        BASE64_URL_SAFE_NO_PAD.encode(json!({ "sub": sub }).to_string()),
+    )
+}
+
+fn bearer_sub(headers: &HeaderMap) -> Option<u64> {
+    let token = headers
+        .get("Authorization")?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")?;
+
+    crate::jwt::parse(token).ok().map(|claims| claims.sub)
+}
+
+#[derive(Deserialize)]
+struct UsernameQuery {
+    username: String,
+}
+
+async fn find_users(
+    State(mock): State<MockDuolingo>,
+    Query(query): Query<UsernameQuery>,
+) -> Json<serde_json::Value> {
+    let users = mock.users.lock().unwrap();
+    let found = users
+        .values()
+        .filter(|user| user.username.eq_ignore_ascii_case(&query.username))
+        .map(MockUser::to_json)
+        .collect::<Vec<_>>();
+
+    Json(json!({ "users": found }))
+}
+
+#[derive(Deserialize)]
+struct FieldsQuery {
+    fields: Option<String>,
+}
+
+async fn get_user(
+    State(mock): State<MockDuolingo>,
+    Path(uid): Path<u64>,
+    Query(query): Query<FieldsQuery>,
+    headers: HeaderMap,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    // profiles are public, but a token for somebody else is rejected
+    if headers.contains_key("Authorization") && bearer_sub(&headers) != Some(uid) {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let user = mock.user(uid).ok_or(StatusCode::NOT_FOUND)?;
+
+    let json = user.to_json();
+    match query.fields {
+        Some(fields) => Ok(Json(
+            fields
+                .split(',')
+                .filter_map(|field| Some((field.to_string(), json.get(field)?.clone())))
+                .collect::<serde_json::Map<_, _>>()
+                .into(),
+        )),
+        None => Ok(Json(json)),
+    }
+}
+
+#[derive(Deserialize)]
+struct BioPatch {
+    bio: String,
+}
+
+async fn patch_user(
+    State(mock): State<MockDuolingo>,
+    Path(uid): Path<u64>,
+    headers: HeaderMap,
+    Json(patch): Json<BioPatch>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    if bearer_sub(&headers) != Some(uid) {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    let mut users = mock.users.lock().unwrap();
+    let user = users.get_mut(&uid).ok_or(StatusCode::NOT_FOUND)?;
+    user.bio = patch.bio;
+
+    Ok(Json(user.to_json()))
+}
+
+#[tokio::test]
+async fn test_mock_duolingo_client() {
+    use crate::duolingo::{
+        add_address_to_profile, get_user_uid_and_maybe_address, DuolingoApi, DuolingoClient,
+    };
+    use ethers::types::Address;
+
+    let mock = MockDuolingo::new([MockUser::new(1001, "alice")
+        .with_bio("hola")
+        .with_total_xp(250)]);
+    let base_url = mock.clone().spawn().unwrap();
+    let duolingo = DuolingoClient::new(reqwest::Client::new(), base_url);
+
+    let user = duolingo
+        .get_user_by_username("alice")
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(user.id, 1001);
+    assert_eq!(user.bio, "hola");
+    assert!(duolingo
+        .get_user_by_username("bob")
+        .await
+        .unwrap()
+        .is_none());
+    assert_eq!(duolingo.get_user_total_xp(1001).await.unwrap(), 250);
+    assert_eq!(
+        get_user_uid_and_maybe_address(&duolingo, "alice")
+            .await
+            .unwrap(),
+        Some((1001, None))
+    );
+
+    let address: Address = "0x69AA0361Dbb0527d4F1e5312403Bd41788fe61Fe"
+        .parse()
+        .unwrap();
+    add_address_to_profile(&duolingo, 1001, &jwt_for(1001), address)
+        .await
+        .unwrap();
+    assert_eq!(
+        mock.user(1001).unwrap().bio,
+        "hola 0x69AA0361Dbb0527d4F1e5312403Bd41788fe61Fe"
+    );
+    assert_eq!(
+        get_user_uid_and_maybe_address(&duolingo, "alice")
+            .await
+            .unwrap(),
+        Some((1001, Some(address)))
+    );
+    let profile = duolingo.get_public_profile(1001).await.unwrap().unwrap();
+    assert_eq!(
+        (
+            profile.username.as_str(),
+            profile.total_xp,
+            profile.address()
+        ),
+        ("alice", 250, Some(address))
+    );
+    assert!(duolingo.get_public_profile(1002).await.unwrap().is_none());
+
+    assert!(duolingo
+        .update_bio(1001, &jwt_for(1002), "stolen")
+        .await
+        .is_err());
+    assert_eq!(
+        mock.user(1001).unwrap().bio,
+        "hola 0x69AA0361Dbb0527d4F1e5312403Bd41788fe61Fe"
+    );
+}
@@ -0,0 +1,378 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+};
+
+use ethers::{
+    types::{Address, TxHash},
+    utils::to_checksum,
+};
+use serde::Serialize;
+
+use crate::{
+    tx::{explain_revert, TxOutcome},
+    Connections, XpProgress,
+};
+
+/// How far Duolingo XP can get ahead of the contract before it's reported.
+pub const DEFAULT_XP_THRESHOLD: u64 = 100;
+
+/// A way in which the contract, Duolingo and the registry disagree.
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Drift {
+    /// The registry doesn't know what the contract has for a linked account.
+    RegistryOutOfDate {
+        uid: u64,
+        in_registry: Option<Address>,
+        registered: Option<Address>,
+    },
+    /// The UID is registered, but its Duolingo account is gone.
+    DuolingoUserMissing { uid: u64 },
+    /// The registered address is neither in the bio nor verified with
+    /// /linkwallet.
+    AddressNotInProfile {
+        uid: u64,
+        username: String,
+        registered: Address,
+        in_profile: Option<Address>,
+    },
+    XpBehind {
+        uid: u64,
+        username: String,
+        reported: u64,
+        total_xp: u64,
+    },
+    /// Only `userRegister` checks that an address is unused, so a bad
+    /// `userUpdateAddress` can leave two UIDs paying out to one address.
+    SharedAddress { address: Address, uids: Vec<u64> },
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum Fix {
+    /// `tx_hash` is `None` if only the registry had to change.
+    Fixed {
+        tx_hash: Option<TxHash>,
+    },
+    Failed {
+        reason: String,
+    },
+    Skipped {
+        reason: String,
+    },
+}
+
+#[derive(Debug, Serialize)]
+pub struct Finding {
+    #[serde(flatten)]
+    pub drift: Drift,
+    /// Only set when fixing.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fix: Option<Fix>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub checked: usize,
+    /// UIDs that couldn't be checked, e.g. because Duolingo didn't answer.
+    pub unchecked: Vec<u64>,
+    pub findings: Vec<Finding>,
+}
+
+/// Checks every UID the index has as registered, plus every account the
+/// registry thinks is registered, against the contract and Duolingo. With
+/// `fix`, brings the registry in line with the contract, moves registrations
+/// to the address in the user's profile and reports XP that's at least
+/// `xp_threshold` behind. Anything that needs a person to decide is left
+/// alone.
+pub async fn reconcile(
+    connections: &Connections,
+    fix: bool,
+    xp_threshold: u64,
+) -> anyhow::Result<Report> {
+    let registry = &connections.registry;
+
+    let uids = registry
+        .indexed_users()
+        .await?
+        .into_iter()
+        .map(|user| user.duolingo_uid)
+        .chain(
+            registry
+                .registered_accounts()
+                .await?
+                .into_iter()
+                .map(|account| account.duolingo_uid),
+        )
+        .collect::<BTreeSet<_>>();
+
+    let mut report = Report {
+        checked: 0,
+        unchecked: vec![],
+        findings: vec![],
+    };
+    let mut uids_by_address = BTreeMap::<Address, Vec<u64>>::new();
+
+    for uid in uids {
+        match check(connections, uid, fix, xp_threshold, &mut report.findings).await {
+            Ok(Some(address)) => {
+                report.checked += 1;
+                uids_by_address.entry(address).or_default().push(uid);
+            }
+            Ok(None) => report.checked += 1,
+            Err(e) => {
+                log::warn!("Failed to reconcile {uid}: {e:#}");
+                report.unchecked.push(uid);
+            }
+        }
+    }
+
+    for (address, uids) in uids_by_address {
+        if uids.len() > 1 {
+            report.findings.push(Finding {
+                drift: Drift::SharedAddress { address, uids },
+                fix: fix.then(|| Fix::Skipped {
+                    reason: "pick the UID the address belongs to".to_string(),
+                }),
+            });
+        }
+    }
+
+    Ok(report)
+}
+
+/// Returns the address `uid` is registered with, if any.
+async fn check(
+    connections: &Connections,
+    uid: u64,
+    fix: bool,
+    xp_threshold: u64,
+    findings: &mut Vec<Finding>,
+) -> anyhow::Result<Option<Address>> {
+    let (address, xp_in_contract) = connections.contract.users(uid.into()).await?;
+    let registered = (!address.is_zero()).then_some(address);
+    let account = connections.registry.account(uid).await?;
+
+    if let Some(account) = &account {
+        if account.address != registered {
+            let fix = if fix {
+                let xp = registered.map(|_| xp_in_contract.as_u64());
+                Some(
+                    match connections
+                        .registry
+                        .record_contract_state(uid, registered, xp)
+                        .await
+                    {
+                        Ok(()) => Fix::Fixed { tx_hash: None },
+                        Err(e) => Fix::Failed {
+                            reason: format!("{e:#}"),
+                        },
+                    },
+                )
+            } else {
+                None
+            };
+
+            findings.push(Finding {
+                drift: Drift::RegistryOutOfDate {
+                    uid,
+                    in_registry: account.address,
+                    registered,
+                },
+                fix,
+            });
+        }
+    }
+
+    let Some(address) = registered else {
+        return Ok(None);
+    };
+
+    let Some(profile) = connections.duolingo.get_public_profile(uid).await? else {
+        findings.push(Finding {
+            drift: Drift::DuolingoUserMissing { uid },
+            fix: fix.then(|| Fix::Skipped {
+                reason: "unregister it if the account is really gone".to_string(),
+            }),
+        });
+        return Ok(Some(address));
+    };
+
+    let in_profile = account
+        .and_then(|account| account.verified_address)
+        .or(profile.address());
+
+    let mut address_fixed = true;
+    if in_profile != Some(address) {
+        let fix = match (fix, in_profile) {
+            (false, _) => None,
+            (true, None) => Some(Fix::Skipped {
+                reason: "there's no address to move the registration to".to_string(),
+            }),
+            (true, Some(in_profile)) => Some(move_registration(connections, uid, in_profile).await),
+        };
+        address_fixed = matches!(fix, Some(Fix::Fixed { .. }));
+
+        findings.push(Finding {
+            drift: Drift::AddressNotInProfile {
+                uid,
+                username: profile.username.clone(),
+                registered: address,
+                in_profile,
+            },
+            fix,
+        });
+    }
+
+    if let XpProgress::Gained(gained) = XpProgress::new(profile.total_xp, xp_in_contract) {
+        if gained >= xp_threshold {
+            let fix = match (fix, address_fixed) {
+                (false, _) => None,
+                (true, false) => Some(Fix::Skipped {
+                    reason: "the address has to be sorted out first".to_string(),
+                }),
+                (true, true) => Some(settle(connections.report_xp(uid, profile.total_xp).await)),
+            };
+
+            findings.push(Finding {
+                drift: Drift::XpBehind {
+                    uid,
+                    username: profile.username,
+                    reported: xp_in_contract.as_u64(),
+                    total_xp: profile.total_xp,
+                },
+                fix,
+            });
+        }
+    }
+
+    Ok(Some(address))
+}
+
+async fn move_registration(connections: &Connections, uid: u64, address: Address) -> Fix {
+    match connections.contract.address_to_uid(address).await {
+        Ok(owner) if owner.is_zero() => {}
+        Ok(owner) => {
+            return Fix::Skipped {
+                reason: format!("the address in the profile is registered to UID {owner}"),
+            }
+        }
+        Err(e) => {
+            return Fix::Failed {
+                reason: e.to_string(),
+            }
+        }
+    }
+
+    settle(connections.update_address(uid, address).await)
+}
+
+fn settle(outcome: anyhow::Result<TxOutcome>) -> Fix {
+    match outcome {
+        Ok(TxOutcome::Confirmed(receipt)) => Fix::Fixed {
+            tx_hash: Some(receipt.transaction_hash),
+        },
+        Ok(TxOutcome::Reverted { reason, .. }) => Fix::Failed {
+            reason: explain_revert(reason.as_deref()),
+        },
+        Err(e) => Fix::Failed {
+            reason: format!("{e:#}"),
+        },
+    }
+}
+
+fn address(address: &Option<Address>) -> String {
+    address
+        .map(|a| to_checksum(&a, None))
+        .unwrap_or_else(|| "none".to_string())
+}
+
+impl fmt::Display for Drift {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Drift::RegistryOutOfDate {
+                uid,
+                in_registry,
+                registered,
+            } => write!(
+                f,
+                "UID {uid}: the registry has {}, but the contract has {}",
+                address(in_registry),
+                address(registered)
+            ),
+            Drift::DuolingoUserMissing { uid } => {
+                write!(
+                    f,
+                    "UID {uid}: registered, but there's no such Duolingo user"
+                )
+            }
+            Drift::AddressNotInProfile {
+                uid,
+                username,
+                registered,
+                in_profile,
+            } => write!(
+                f,
+                "{username} (UID {uid}): registered with {}, but their profile has {}",
+                to_checksum(registered, None),
+                address(in_profile)
+            ),
+            Drift::XpBehind {
+                uid,
+                username,
+                reported,
+                total_xp,
+            } => write!(
+                f,
+                "{username} (UID {uid}): {total_xp} XP on Duolingo, {} more than was reported",
+                total_xp - reported
+            ),
+            Drift::SharedAddress { address, uids } => write!(
+                f,
+                "{} is registered to UIDs {}",
+                to_checksum(address, None),
+                uids.iter()
+                    .map(|uid| uid.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.drift)?;
+
+        match &self.fix {
+            None => Ok(()),
+            Some(Fix::Fixed { tx_hash: None }) => write!(f, " (fixed)"),
+            Some(Fix::Fixed {
+                tx_hash: Some(tx_hash),
+            }) => write!(f, " (fixed in {tx_hash:?})"),
+            Some(Fix::Failed { reason }) => write!(f, " (fix failed: {reason})"),
+            Some(Fix::Skipped { reason }) => write!(f, " (not fixed: {reason})"),
+        }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Checked {} UIDs", self.checked)?;
+        if !self.unchecked.is_empty() {
+            write!(f, ", couldn't check {}", self.unchecked.len())?;
+        }
+
+        if self.findings.is_empty() {
+            return writeln!(f, ", everything matches.");
+        }
+
+        writeln!(f, ", found {} problems:", self.findings.len())?;
+        for finding in &self.findings {
+            writeln!(f, "- {finding}")?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,1009 @@
+use std::path::Path;
+
+use chrono::{DateTime, TimeZone, Utc};
+use ethers::types::{Address, TxHash, U256};
+use sqlx::{
+    sqlite::{SqliteConnectOptions, SqliteRow},
+    Row, Sqlite, SqlitePool, Transaction,
+};
+use teloxide::types::UserId;
+
+/// Applied in order; the index of the last applied migration is tracked in
+/// `PRAGMA user_version`.
+const MIGRATIONS: &[&str] = &[
+    r#"
+CREATE TABLE accounts (
+    duolingo_uid INTEGER PRIMARY KEY,
+    telegram_user_id INTEGER,
+    username TEXT NOT NULL
+);
+"#,
+    r#"
+ALTER TABLE accounts ADD COLUMN address TEXT;
+ALTER TABLE accounts ADD COLUMN last_reported_xp INTEGER;
+ALTER TABLE accounts ADD COLUMN last_tx_hash TEXT;
+ALTER TABLE accounts ADD COLUMN linked_at INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE accounts ADD COLUMN updated_at INTEGER NOT NULL DEFAULT 0;
+CREATE INDEX accounts_telegram_user_id ON accounts (telegram_user_id);
+"#,
+    r#"
+ALTER TABLE accounts ADD COLUMN verified_address TEXT;
+"#,
+    r#"
+CREATE TABLE indexer (
+    contract TEXT NOT NULL,
+    next_block INTEGER NOT NULL
+);
+CREATE TABLE contract_events (
+    block_number INTEGER NOT NULL,
+    log_index INTEGER NOT NULL,
+    tx_hash TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    duolingo_uid INTEGER NOT NULL,
+    address TEXT NOT NULL,
+    amount TEXT,
+    PRIMARY KEY (block_number, log_index)
+);
+CREATE INDEX contract_events_duolingo_uid ON contract_events (duolingo_uid);
+CREATE TABLE contract_users (
+    duolingo_uid INTEGER PRIMARY KEY,
+    address TEXT,
+    minted TEXT NOT NULL DEFAULT '0',
+    registered_block INTEGER NOT NULL,
+    updated_block INTEGER NOT NULL
+);
+CREATE INDEX contract_users_address ON contract_users (address);
+"#,
+];
+
+/// A Duolingo account that has been linked through the bot, or registered by
+/// an admin.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Account {
+    pub duolingo_uid: u64,
+    /// `None` until someone links the account from Telegram.
+    pub telegram_user_id: Option<UserId>,
+    pub username: String,
+    /// The address registered with the contract, if any.
+    pub address: Option<Address>,
+    /// An address the user proved they control by signing a message while
+    /// linking. Takes precedence over any address in their Duolingo bio.
+    pub verified_address: Option<Address>,
+    pub last_reported_xp: Option<u64>,
+    pub last_tx_hash: Option<TxHash>,
+    pub linked_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Account {
+    fn from_row(row: &SqliteRow) -> anyhow::Result<Self> {
+        fn timestamp(row: &SqliteRow, column: &str) -> anyhow::Result<DateTime<Utc>> {
+            Utc.timestamp_opt(row.try_get(column)?, 0)
+                .single()
+                .ok_or_else(|| anyhow::anyhow!("Invalid timestamp in column {column}"))
+        }
+
+        Ok(Self {
+            duolingo_uid: row.try_get::<i64, _>("duolingo_uid")? as u64,
+            telegram_user_id: row
+                .try_get::<Option<i64>, _>("telegram_user_id")?
+                .map(|id| UserId(id as u64)),
+            username: row.try_get("username")?,
+            address: row
+                .try_get::<Option<String>, _>("address")?
+                .map(|a| a.parse())
+                .transpose()?,
+            verified_address: row
+                .try_get::<Option<String>, _>("verified_address")?
+                .map(|a| a.parse())
+                .transpose()?,
+            last_reported_xp: row
+                .try_get::<Option<i64>, _>("last_reported_xp")?
+                .map(|xp| xp as u64),
+            last_tx_hash: row
+                .try_get::<Option<String>, _>("last_tx_hash")?
+                .map(|h| h.parse())
+                .transpose()?,
+            linked_at: timestamp(row, "linked_at")?,
+            updated_at: timestamp(row, "updated_at")?,
+        })
+    }
+}
+
+/// What the indexer picks out of the contract's logs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ContractEvent {
+    /// `UserRegistrationUpdate`, which has the zero address when a UID is
+    /// unregistered.
+    RegistrationUpdate { uid: u64, address: Address },
+    /// A `Transfer` from the zero address, attributed to the UID by the
+    /// `XpReported` that `_mintXp` emits right after it. Contracts deployed
+    /// before `XpReported` was added don't emit it, and then `uid` is `None`
+    /// and the mint goes to the UID registered with `to`.
+    Mint {
+        uid: Option<u64>,
+        to: Address,
+        amount: U256,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoggedEvent {
+    pub block_number: u64,
+    pub log_index: u64,
+    pub tx_hash: TxHash,
+    pub event: ContractEvent,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    Registered,
+    AddressUpdated,
+    Unregistered,
+    Minted,
+}
+
+impl EventKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Registered => "registered",
+            Self::AddressUpdated => "address_updated",
+            Self::Unregistered => "unregistered",
+            Self::Minted => "minted",
+        }
+    }
+
+    fn parse(kind: &str) -> anyhow::Result<Self> {
+        [
+            Self::Registered,
+            Self::AddressUpdated,
+            Self::Unregistered,
+            Self::Minted,
+        ]
+        .into_iter()
+        .find(|k| k.as_str() == kind)
+        .ok_or_else(|| anyhow::anyhow!("Unknown event kind {kind:?}"))
+    }
+}
+
+/// An indexed event, classified by what it did to the UID.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexedEvent {
+    pub block_number: u64,
+    pub tx_hash: TxHash,
+    pub kind: EventKind,
+    pub duolingo_uid: u64,
+    /// The new address, the address that was unregistered, or where POD was
+    /// minted to.
+    pub address: Address,
+    pub amount: Option<U256>,
+}
+
+impl IndexedEvent {
+    fn from_row(row: &SqliteRow) -> anyhow::Result<Self> {
+        Ok(Self {
+            block_number: row.try_get::<i64, _>("block_number")? as u64,
+            tx_hash: row.try_get::<String, _>("tx_hash")?.parse()?,
+            kind: EventKind::parse(row.try_get("kind")?)?,
+            duolingo_uid: row.try_get::<i64, _>("duolingo_uid")? as u64,
+            address: row.try_get::<String, _>("address")?.parse()?,
+            amount: row
+                .try_get::<Option<String>, _>("amount")?
+                .map(|a| U256::from_dec_str(&a))
+                .transpose()?,
+        })
+    }
+}
+
+/// A UID as the contract's events describe it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexedUser {
+    pub duolingo_uid: u64,
+    /// `None` once the UID has been unregistered.
+    pub address: Option<Address>,
+    /// Everything minted to the UID, across registrations.
+    pub minted: U256,
+    /// The first block the index saw the registration in, which is that of
+    /// the first mint if the registration predates the index.
+    pub registered_block: u64,
+    pub updated_block: u64,
+}
+
+impl IndexedUser {
+    fn from_row(row: &SqliteRow) -> anyhow::Result<Self> {
+        Ok(Self {
+            duolingo_uid: row.try_get::<i64, _>("duolingo_uid")? as u64,
+            address: row
+                .try_get::<Option<String>, _>("address")?
+                .map(|a| a.parse())
+                .transpose()?,
+            minted: U256::from_dec_str(row.try_get("minted")?)?,
+            registered_block: row.try_get::<i64, _>("registered_block")? as u64,
+            updated_block: row.try_get::<i64, _>("updated_block")? as u64,
+        })
+    }
+}
+
+fn now() -> i64 {
+    Utc::now().timestamp()
+}
+
+fn hex<T: std::fmt::Debug>(value: T) -> String {
+    format!("{value:?}")
+}
+
+/// Local record of which Telegram user has proven control of which Duolingo
+/// account, and what the bot last did on-chain for it.
+#[derive(Clone)]
+pub struct Registry {
+    pool: SqlitePool,
+}
+
+impl Registry {
+    pub async fn open(path: &Path) -> anyhow::Result<Self> {
+        let pool = SqlitePool::connect_with(
+            SqliteConnectOptions::new()
+                .filename(path)
+                .create_if_missing(true),
+        )
+        .await?;
+
+        Self::migrate(pool).await
+    }
+
+    #[cfg(test)]
+    pub async fn in_memory() -> Self {
+        // every connection to `:memory:` is a separate database
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+
+        Self::migrate(pool).await.unwrap()
+    }
+
+    async fn migrate(pool: SqlitePool) -> anyhow::Result<Self> {
+        let mut tx = pool.begin().await?;
+
+        let version: i64 = sqlx::query("PRAGMA user_version")
+            .fetch_one(&mut tx)
+            .await?
+            .get(0);
+
+        for migration in MIGRATIONS.iter().skip(version as usize) {
+            sqlx::query(migration).execute(&mut tx).await?;
+        }
+
+        sqlx::query(&format!("PRAGMA user_version = {}", MIGRATIONS.len()))
+            .execute(&mut tx)
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(Self { pool })
+    }
+
+    /// Binds `uid` to `telegram_user_id`, replacing any previous owner and
+    /// forgetting their verified address.
+    pub async fn link(
+        &self,
+        telegram_user_id: UserId,
+        uid: u64,
+        username: &str,
+    ) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+INSERT INTO accounts (duolingo_uid, telegram_user_id, username, linked_at, updated_at)
+VALUES (?1, ?2, ?3, ?4, ?4)
+ON CONFLICT (duolingo_uid) DO UPDATE SET
+    telegram_user_id = excluded.telegram_user_id,
+    username = excluded.username,
+    verified_address = NULL,
+    linked_at = excluded.linked_at,
+    updated_at = excluded.updated_at
+            "#,
+        )
+        .bind(uid as i64)
+        .bind(telegram_user_id.0 as i64)
+        .bind(username)
+        .bind(now())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Keeps track of `uid` without a Telegram user, so that what's done for
+    /// it on-chain is recorded too. Does nothing if it's already known.
+    pub async fn add_unlinked(&self, uid: u64, username: &str) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+INSERT INTO accounts (duolingo_uid, username, linked_at, updated_at)
+VALUES (?1, ?2, ?3, ?3)
+ON CONFLICT (duolingo_uid) DO NOTHING
+            "#,
+        )
+        .bind(uid as i64)
+        .bind(username)
+        .bind(now())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn record_verified_address(&self, uid: u64, address: Address) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+UPDATE accounts
+SET verified_address = ?2, updated_at = ?3
+WHERE duolingo_uid = ?1
+            "#,
+        )
+        .bind(uid as i64)
+        .bind(hex(address))
+        .bind(now())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn account(&self, uid: u64) -> anyhow::Result<Option<Account>> {
+        sqlx::query("SELECT * FROM accounts WHERE duolingo_uid = ?1")
+            .bind(uid as i64)
+            .fetch_optional(&self.pool)
+            .await?
+            .as_ref()
+            .map(Account::from_row)
+            .transpose()
+    }
+
+    pub async fn accounts_for_telegram_user(
+        &self,
+        telegram_user_id: UserId,
+    ) -> anyhow::Result<Vec<Account>> {
+        sqlx::query("SELECT * FROM accounts WHERE telegram_user_id = ?1 ORDER BY linked_at")
+            .bind(telegram_user_id.0 as i64)
+            .fetch_all(&self.pool)
+            .await?
+            .iter()
+            .map(Account::from_row)
+            .collect()
+    }
+
+    /// Accounts that currently have an address registered with the contract.
+    pub async fn registered_accounts(&self) -> anyhow::Result<Vec<Account>> {
+        sqlx::query("SELECT * FROM accounts WHERE address IS NOT NULL ORDER BY duolingo_uid")
+            .fetch_all(&self.pool)
+            .await?
+            .iter()
+            .map(Account::from_row)
+            .collect()
+    }
+
+    pub async fn owner(&self, uid: u64) -> anyhow::Result<Option<UserId>> {
+        Ok(self.account(uid).await?.and_then(|a| a.telegram_user_id))
+    }
+
+    pub async fn record_registration(
+        &self,
+        uid: u64,
+        address: Address,
+        xp: u64,
+        tx_hash: TxHash,
+    ) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+UPDATE accounts
+SET address = ?2, last_reported_xp = ?3, last_tx_hash = ?4, updated_at = ?5
+WHERE duolingo_uid = ?1
+            "#,
+        )
+        .bind(uid as i64)
+        .bind(hex(address))
+        .bind(xp as i64)
+        .bind(hex(tx_hash))
+        .bind(now())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn record_address(
+        &self,
+        uid: u64,
+        address: Address,
+        tx_hash: TxHash,
+    ) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+UPDATE accounts
+SET address = ?2, last_tx_hash = ?3, updated_at = ?4
+WHERE duolingo_uid = ?1
+            "#,
+        )
+        .bind(uid as i64)
+        .bind(hex(address))
+        .bind(hex(tx_hash))
+        .bind(now())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn record_xp(&self, uid: u64, xp: u64, tx_hash: TxHash) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+UPDATE accounts
+SET last_reported_xp = ?2, last_tx_hash = ?3, updated_at = ?4
+WHERE duolingo_uid = ?1
+            "#,
+        )
+        .bind(uid as i64)
+        .bind(xp as i64)
+        .bind(hex(tx_hash))
+        .bind(now())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn record_unregistration(&self, uid: u64, tx_hash: TxHash) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+UPDATE accounts
+SET address = NULL, last_reported_xp = NULL, last_tx_hash = ?2, updated_at = ?3
+WHERE duolingo_uid = ?1
+            "#,
+        )
+        .bind(uid as i64)
+        .bind(hex(tx_hash))
+        .bind(now())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Overwrites what the registry thinks is on-chain, for when it has
+    /// drifted from the contract. Keeps the last transaction as it was.
+    pub async fn record_contract_state(
+        &self,
+        uid: u64,
+        address: Option<Address>,
+        xp: Option<u64>,
+    ) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"
+UPDATE accounts
+SET address = ?2, last_reported_xp = ?3, updated_at = ?4
+WHERE duolingo_uid = ?1
+            "#,
+        )
+        .bind(uid as i64)
+        .bind(address.map(hex))
+        .bind(xp.map(|xp| xp as i64))
+        .bind(now())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// The first block the indexer hasn't looked at for `contract`. Anything
+    /// indexed for another contract is forgotten.
+    pub async fn index_cursor(&self, contract: Address) -> anyhow::Result<Option<u64>> {
+        let mut tx = self.pool.begin().await?;
+
+        let cursor = sqlx::query("SELECT contract, next_block FROM indexer")
+            .fetch_optional(&mut tx)
+            .await?;
+        let next_block = match cursor {
+            Some(row) if row.try_get::<String, _>("contract")? == hex(contract) => {
+                Some(row.try_get::<i64, _>("next_block")? as u64)
+            }
+            Some(_) => {
+                log::warn!("Dropping events indexed for a different contract");
+                sqlx::query(
+                    "DELETE FROM indexer; DELETE FROM contract_events; DELETE FROM contract_users;",
+                )
+                .execute(&mut tx)
+                .await?;
+                None
+            }
+            None => None,
+        };
+
+        tx.commit().await?;
+
+        Ok(next_block)
+    }
+
+    /// Applies `events` in order and moves the cursor on to `next_block`, so
+    /// a batch is either indexed completely or not at all.
+    pub async fn index_events(
+        &self,
+        contract: Address,
+        events: &[LoggedEvent],
+        next_block: u64,
+    ) -> anyhow::Result<()> {
+        let mut tx = self.pool.begin().await?;
+
+        for event in events {
+            apply_event(&mut tx, event).await?;
+        }
+
+        sqlx::query("DELETE FROM indexer").execute(&mut tx).await?;
+        sqlx::query("INSERT INTO indexer (contract, next_block) VALUES (?1, ?2)")
+            .bind(hex(contract))
+            .bind(next_block as i64)
+            .execute(&mut tx)
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    pub async fn indexed_user(&self, uid: u64) -> anyhow::Result<Option<IndexedUser>> {
+        sqlx::query("SELECT * FROM contract_users WHERE duolingo_uid = ?1")
+            .bind(uid as i64)
+            .fetch_optional(&self.pool)
+            .await?
+            .as_ref()
+            .map(IndexedUser::from_row)
+            .transpose()
+    }
+
+    /// UIDs that are registered with the contract as of the last indexed
+    /// block.
+    pub async fn indexed_users(&self) -> anyhow::Result<Vec<IndexedUser>> {
+        sqlx::query("SELECT * FROM contract_users WHERE address IS NOT NULL ORDER BY duolingo_uid")
+            .fetch_all(&self.pool)
+            .await?
+            .iter()
+            .map(IndexedUser::from_row)
+            .collect()
+    }
+
+    pub async fn indexed_events(&self, uid: u64) -> anyhow::Result<Vec<IndexedEvent>> {
+        sqlx::query(
+            "SELECT * FROM contract_events WHERE duolingo_uid = ?1 ORDER BY block_number, log_index",
+        )
+        .bind(uid as i64)
+        .fetch_all(&self.pool)
+        .await?
+        .iter()
+        .map(IndexedEvent::from_row)
+        .collect()
+    }
+}
+
+async fn apply_event(tx: &mut Transaction<'_, Sqlite>, logged: &LoggedEvent) -> anyhow::Result<()> {
+    let block_number = logged.block_number as i64;
+
+    let (kind, uid, address, amount) = match logged.event {
+        ContractEvent::RegistrationUpdate { uid, address } => {
+            let previous =
+                sqlx::query("SELECT address FROM contract_users WHERE duolingo_uid = ?1")
+                    .bind(uid as i64)
+                    .fetch_optional(&mut *tx)
+                    .await?
+                    .and_then(|row| row.get::<Option<String>, _>("address"))
+                    .map(|a| a.parse::<Address>())
+                    .transpose()?;
+
+            if address.is_zero() {
+                sqlx::query(
+                    "UPDATE contract_users SET address = NULL, updated_block = ?2 WHERE duolingo_uid = ?1",
+                )
+                .bind(uid as i64)
+                .bind(block_number)
+                .execute(&mut *tx)
+                .await?;
+
+                // the zero address if the registration predates the index
+                let previous = previous.unwrap_or(address);
+                (EventKind::Unregistered, uid, previous, None)
+            } else {
+                sqlx::query(
+                    r#"
+INSERT INTO contract_users (duolingo_uid, address, registered_block, updated_block)
+VALUES (?1, ?2, ?3, ?3)
+ON CONFLICT (duolingo_uid) DO UPDATE SET
+    address = excluded.address,
+    registered_block = CASE
+        WHEN address IS NULL THEN excluded.registered_block
+        ELSE registered_block
+    END,
+    updated_block = excluded.updated_block
+                    "#,
+                )
+                .bind(uid as i64)
+                .bind(hex(address))
+                .bind(block_number)
+                .execute(&mut *tx)
+                .await?;
+
+                let kind = match previous {
+                    Some(_) => EventKind::AddressUpdated,
+                    None => EventKind::Registered,
+                };
+                (kind, uid, address, None)
+            }
+        }
+        ContractEvent::Mint { uid, to, amount } => {
+            let uid = match uid {
+                Some(uid) => uid,
+                None => {
+                    let uids: Vec<i64> =
+                        sqlx::query("SELECT duolingo_uid FROM contract_users WHERE address = ?1")
+                            .bind(hex(to))
+                            .fetch_all(&mut *tx)
+                            .await?
+                            .iter()
+                            .map(|row| row.try_get("duolingo_uid"))
+                            .collect::<Result<_, _>>()?;
+
+                    match uids[..] {
+                        [uid] => uid as u64,
+                        _ => {
+                            log::warn!(
+                                "Not indexing the mint to {to:?} in {:?}: {} UIDs are registered with it",
+                                logged.tx_hash,
+                                uids.len()
+                            );
+                            return Ok(());
+                        }
+                    }
+                }
+            };
+
+            let minted = sqlx::query("SELECT minted FROM contract_users WHERE duolingo_uid = ?1")
+                .bind(uid as i64)
+                .fetch_optional(&mut *tx)
+                .await?
+                .map(|row| row.try_get::<String, _>("minted"))
+                .transpose()?
+                .map(|minted| U256::from_dec_str(&minted))
+                .transpose()?;
+
+            match minted {
+                Some(minted) => {
+                    sqlx::query("UPDATE contract_users SET minted = ?2 WHERE duolingo_uid = ?1")
+                        .bind(uid as i64)
+                        .bind((minted + amount).to_string())
+                        .execute(&mut *tx)
+                        .await?;
+                }
+                // only registered UIDs are minted to, so this one was
+                // registered with `to` before the index started
+                None => {
+                    sqlx::query(
+                        r#"
+INSERT INTO contract_users (duolingo_uid, address, minted, registered_block, updated_block)
+VALUES (?1, ?2, ?3, ?4, ?4)
+                        "#,
+                    )
+                    .bind(uid as i64)
+                    .bind(hex(to))
+                    .bind(amount.to_string())
+                    .bind(block_number)
+                    .execute(&mut *tx)
+                    .await?;
+                }
+            }
+
+            (EventKind::Minted, uid, to, Some(amount))
+        }
+    };
+
+    sqlx::query(
+        r#"
+INSERT INTO contract_events (block_number, log_index, tx_hash, kind, duolingo_uid, address, amount)
+VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+        "#,
+    )
+    .bind(block_number)
+    .bind(logged.log_index as i64)
+    .bind(hex(logged.tx_hash))
+    .bind(kind.as_str())
+    .bind(uid as i64)
+    .bind(hex(address))
+    .bind(amount.map(|a| a.to_string()))
+    .execute(&mut *tx)
+    .await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_registry_tracks_unlinked_registrations() {
+    let registry = Registry::in_memory().await;
+    let address = Address::repeat_byte(0xaa);
+
+    registry.add_unlinked(1001, "alice").await.unwrap();
+    registry
+        .record_registration(1001, address, 100, TxHash::repeat_byte(1))
+        .await
+        .unwrap();
+    let account = registry.account(1001).await.unwrap().unwrap();
+    assert_eq!(account.telegram_user_id, None);
+    assert_eq!(account.address, Some(address));
+    assert_eq!(registry.owner(1001).await.unwrap(), None);
+    assert_eq!(registry.registered_accounts().await.unwrap(), vec![account]);
+
+    // linking later claims it, and doesn't lose the registration
+    registry.link(UserId(7001), 1001, "alice").await.unwrap();
+    registry.add_unlinked(1001, "alice").await.unwrap();
+    let account = registry.account(1001).await.unwrap().unwrap();
+    assert_eq!(account.telegram_user_id, Some(UserId(7001)));
+    assert_eq!(account.address, Some(address));
+}
+
+#[tokio::test]
+async fn test_registry_records_account_lifecycle() {
+    let registry = Registry::in_memory().await;
+    let address: Address = "0x69AA0361Dbb0527d4F1e5312403Bd41788fe61Fe"
+        .parse()
+        .unwrap();
+
+    assert_eq!(registry.account(1001).await.unwrap(), None);
+
+    registry.link(UserId(7001), 1001, "alice").await.unwrap();
+    let account = registry.account(1001).await.unwrap().unwrap();
+    assert_eq!(account.telegram_user_id, Some(UserId(7001)));
+    assert_eq!(account.username, "alice");
+    assert_eq!(account.address, None);
+    assert_eq!(account.verified_address, None);
+
+    registry
+        .record_verified_address(1001, address)
+        .await
+        .unwrap();
+    assert_eq!(
+        registry
+            .account(1001)
+            .await
+            .unwrap()
+            .unwrap()
+            .verified_address,
+        Some(address)
+    );
+
+    registry
+        .record_registration(1001, address, 100, TxHash::repeat_byte(1))
+        .await
+        .unwrap();
+    registry
+        .record_xp(1001, 130, TxHash::repeat_byte(2))
+        .await
+        .unwrap();
+    let account = registry.account(1001).await.unwrap().unwrap();
+    assert_eq!(account.address, Some(address));
+    assert_eq!(account.last_reported_xp, Some(130));
+    assert_eq!(account.last_tx_hash, Some(TxHash::repeat_byte(2)));
+    assert_eq!(registry.registered_accounts().await.unwrap(), vec![account]);
+
+    // relinking from another Telegram account keeps the on-chain record but
+    // not the previous owner's proof of address
+    registry.link(UserId(7002), 1001, "alice").await.unwrap();
+    assert_eq!(
+        registry
+            .account(1001)
+            .await
+            .unwrap()
+            .unwrap()
+            .verified_address,
+        None
+    );
+    assert_eq!(registry.owner(1001).await.unwrap(), Some(UserId(7002)));
+    assert!(registry
+        .accounts_for_telegram_user(UserId(7001))
+        .await
+        .unwrap()
+        .is_empty());
+    assert_eq!(
+        registry
+            .accounts_for_telegram_user(UserId(7002))
+            .await
+            .unwrap()
+            .len(),
+        1
+    );
+    assert_eq!(
+        registry.account(1001).await.unwrap().unwrap().address,
+        Some(address)
+    );
+
+    registry
+        .record_unregistration(1001, TxHash::repeat_byte(3))
+        .await
+        .unwrap();
+    let account = registry.account(1001).await.unwrap().unwrap();
+    assert_eq!(account.address, None);
+    assert_eq!(account.last_reported_xp, None);
+    assert!(registry.registered_accounts().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_registry_indexes_contract_events() {
+    let registry = Registry::in_memory().await;
+    let contract = Address::repeat_byte(0xcc);
+    let alice = Address::repeat_byte(0xaa);
+    let alice_new = Address::repeat_byte(0xab);
+    let logged = |block_number, event| LoggedEvent {
+        block_number,
+        log_index: 0,
+        tx_hash: TxHash::repeat_byte(block_number as u8),
+        event,
+    };
+    // as the deployed contract emits them, without `XpReported`
+    let mint = |to, pod: u64| ContractEvent::Mint {
+        uid: None,
+        to,
+        amount: U256::exp10(18) * pod,
+    };
+
+    assert_eq!(registry.index_cursor(contract).await.unwrap(), None);
+
+    registry
+        .index_events(
+            contract,
+            &[
+                logged(
+                    10,
+                    ContractEvent::RegistrationUpdate {
+                        uid: 1001,
+                        address: alice,
+                    },
+                ),
+                logged(11, mint(alice, 30)),
+                logged(
+                    13,
+                    ContractEvent::RegistrationUpdate {
+                        uid: 1001,
+                        address: alice_new,
+                    },
+                ),
+                logged(15, mint(alice_new, 20)),
+            ],
+            16,
+        )
+        .await
+        .unwrap();
+    assert_eq!(registry.index_cursor(contract).await.unwrap(), Some(16));
+
+    let user = registry.indexed_user(1001).await.unwrap().unwrap();
+    assert_eq!(user.address, Some(alice_new));
+    assert_eq!(user.minted, U256::exp10(18) * 50);
+    assert_eq!((user.registered_block, user.updated_block), (10, 13));
+    assert_eq!(registry.indexed_users().await.unwrap(), vec![user]);
+
+    registry
+        .index_events(
+            contract,
+            &[logged(
+                20,
+                ContractEvent::RegistrationUpdate {
+                    uid: 1001,
+                    address: Address::zero(),
+                },
+            )],
+            21,
+        )
+        .await
+        .unwrap();
+    assert!(registry.indexed_users().await.unwrap().is_empty());
+
+    let history = registry.indexed_events(1001).await.unwrap();
+    assert_eq!(
+        history
+            .iter()
+            .map(|e| (e.block_number, e.kind, e.address))
+            .collect::<Vec<_>>(),
+        vec![
+            (10, EventKind::Registered, alice),
+            (11, EventKind::Minted, alice),
+            (13, EventKind::AddressUpdated, alice_new),
+            (15, EventKind::Minted, alice_new),
+            (20, EventKind::Unregistered, alice_new),
+        ]
+    );
+
+    // the index follows one contract at a time
+    assert_eq!(
+        registry
+            .index_cursor(Address::repeat_byte(0xdd))
+            .await
+            .unwrap(),
+        None
+    );
+    assert_eq!(registry.indexed_user(1001).await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn test_registry_credits_mints_to_the_reported_uid() {
+    let registry = Registry::in_memory().await;
+    let contract = Address::repeat_byte(0xcc);
+    let shared = Address::repeat_byte(0xaa);
+    let logged = |block_number, event| LoggedEvent {
+        block_number,
+        log_index: 0,
+        tx_hash: TxHash::repeat_byte(block_number as u8),
+        event,
+    };
+    let mint = |uid, pod: u64| ContractEvent::Mint {
+        uid: Some(uid),
+        to: shared,
+        amount: U256::exp10(18) * pod,
+    };
+
+    registry
+        .index_events(
+            contract,
+            &[
+                // registered before the index started
+                logged(5, mint(1003, 4)),
+                logged(
+                    10,
+                    ContractEvent::RegistrationUpdate {
+                        uid: 1001,
+                        address: shared,
+                    },
+                ),
+                // `userUpdateAddress` doesn't stop two UIDs sharing an address
+                logged(
+                    11,
+                    ContractEvent::RegistrationUpdate {
+                        uid: 1002,
+                        address: shared,
+                    },
+                ),
+                logged(12, mint(1002, 20)),
+                logged(13, mint(1001, 30)),
+                logged(14, mint(1002, 5)),
+                // without a report, there's no telling whose it is
+                logged(
+                    15,
+                    ContractEvent::Mint {
+                        uid: None,
+                        to: shared,
+                        amount: U256::exp10(18) * 7,
+                    },
+                ),
+            ],
+            16,
+        )
+        .await
+        .unwrap();
+
+    for (uid, pod) in [(1001, 30), (1002, 25), (1003, 4)] {
+        let user = registry.indexed_user(uid).await.unwrap().unwrap();
+        assert_eq!(user.address, Some(shared));
+        assert_eq!(user.minted, U256::exp10(18) * pod, "UID {uid}");
+    }
+
+    let history = registry.indexed_events(1003).await.unwrap();
+    assert_eq!(
+        history
+            .iter()
+            .map(|e| (e.block_number, e.kind, e.duolingo_uid))
+            .collect::<Vec<_>>(),
+        vec![(5, EventKind::Minted, 1003)]
+    );
+    assert_eq!(
+        registry
+            .indexed_user(1003)
+            .await
+            .unwrap()
+            .unwrap()
+            .registered_block,
+        5
+    );
+}
@@ -0,0 +1,119 @@
+use std::sync::{Arc, Mutex};
+
+use teloxide::{
+    prelude::*,
+    types::{MessageId, ParseMode},
+    RequestError,
+};
+
+/// Hands out the status messages for one update, and keeps track of the ones
+/// a handler dropped without finishing so they can be cleaned up afterwards.
+#[derive(Clone, Default)]
+pub struct StatusMessages {
+    abandoned: Arc<Mutex<Vec<(ChatId, MessageId)>>>,
+}
+
+impl StatusMessages {
+    pub async fn show(
+        &self,
+        bot: &Bot,
+        chat_id: ChatId,
+        text: impl Into<String>,
+    ) -> Result<StatusMessage, RequestError> {
+        let message = bot
+            .send_message(chat_id, text)
+            .parse_mode(ParseMode::Html)
+            .await?;
+
+        Ok(StatusMessage {
+            bot: bot.clone(),
+            chat_id,
+            id: message.id,
+            owner: self.clone(),
+            settled: false,
+        })
+    }
+
+    /// Turns the most recent abandoned status message into `text`, deleting
+    /// any others. Returns `false` if there was nothing to turn.
+    pub async fn fail(&self, bot: &Bot, text: &str) -> bool {
+        let mut abandoned = self.take();
+        let Some((chat_id, id)) = abandoned.pop() else {
+            return false;
+        };
+
+        delete_all(bot, abandoned).await;
+
+        match bot
+            .edit_message_text(chat_id, id, text)
+            .parse_mode(ParseMode::Html)
+            .await
+        {
+            Ok(_) => true,
+            Err(e) => {
+                log::warn!("Failed to show an error in a status message: {e}");
+                let _ = bot.delete_message(chat_id, id).await;
+                false
+            }
+        }
+    }
+
+    /// Deletes every abandoned status message.
+    pub async fn clean_up(&self, bot: &Bot) {
+        delete_all(bot, self.take()).await;
+    }
+
+    fn take(&self) -> Vec<(ChatId, MessageId)> {
+        std::mem::take(&mut *self.abandoned.lock().unwrap())
+    }
+}
+
+async fn delete_all(bot: &Bot, messages: Vec<(ChatId, MessageId)>) {
+    for (chat_id, id) in messages {
+        if let Err(e) = bot.delete_message(chat_id, id).await {
+            log::warn!("Failed to delete a status message: {e}");
+        }
+    }
+}
+
+/// A message that shows what the bot is doing and is edited in place as the
+/// work progresses. Dropping it without calling [`StatusMessage::finish`]
+/// leaves it to [`StatusMessages`] to clean up.
+pub struct StatusMessage {
+    bot: Bot,
+    chat_id: ChatId,
+    id: MessageId,
+    owner: StatusMessages,
+    settled: bool,
+}
+
+impl StatusMessage {
+    pub async fn set(&mut self, text: impl Into<String>) -> Result<(), RequestError> {
+        self.bot
+            .edit_message_text(self.chat_id, self.id, text)
+            .parse_mode(ParseMode::Html)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Leaves the message in the chat showing `text`.
+    pub async fn finish(mut self, text: impl Into<String>) -> Result<(), RequestError> {
+        self.set(text).await?;
+        self.settled = true;
+
+        Ok(())
+    }
+}
+
+impl Drop for StatusMessage {
+    fn drop(&mut self) {
+        if !self.settled {
+            self.owner
+                .abandoned
+                .lock()
+                .unwrap()
+                .push((self.chat_id, self.id));
+        }
+    }
+}
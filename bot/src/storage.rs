@@ -0,0 +1,35 @@
+use std::{path::Path, sync::Arc};
+
+use clap::ValueEnum;
+use serde::Deserialize;
+use teloxide::dispatching::dialogue::{
+    serializer::Json, ErasedStorage, InMemStorage, SqliteStorage, Storage,
+};
+
+use crate::ChatState;
+
+pub type DialogueStorage = ErasedStorage<ChatState>;
+
+#[derive(Clone, Copy, Debug, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageKind {
+    /// Dialogue state is lost when the bot restarts.
+    Memory,
+    /// Dialogue state is kept in a local SQLite database.
+    Sqlite,
+}
+
+pub async fn open_dialogue_storage(
+    kind: StorageKind,
+    db_path: &Path,
+) -> anyhow::Result<Arc<DialogueStorage>> {
+    Ok(match kind {
+        StorageKind::Memory => InMemStorage::<ChatState>::new().erase(),
+        StorageKind::Sqlite => {
+            let db_path = db_path
+                .to_str()
+                .ok_or_else(|| anyhow::anyhow!("Database path must be valid UTF-8"))?;
+            SqliteStorage::open(db_path, Json).await?.erase()
+        }
+    })
+}
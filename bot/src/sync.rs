@@ -0,0 +1,143 @@
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+
+use teloxide::{prelude::*, types::ParseMode};
+use tokio::{task::JoinSet, time::MissedTickBehavior};
+
+use crate::{
+    tx::{minted_pod, tx_link, TxOutcome},
+    Connections, XpProgress,
+};
+
+/// Reports XP for every registered UID every `interval`, so nobody has to
+/// remember to send `/update`.
+pub fn spawn(bot: Bot, connections: Arc<Connections>, interval: Duration, notify: bool) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            ticker.tick().await;
+
+            match sync_once(&bot, &connections, notify).await {
+                Ok(minted) => log::info!("XP sync finished, minted for {minted} UIDs"),
+                Err(e) => log::error!("XP sync failed: {e:#}"),
+            }
+        }
+    });
+}
+
+/// Walks the UIDs the event index has as registered, which include ones
+/// registered without the bot, plus the linked accounts the registry has as
+/// registered, in case indexing is off or still catching up. Returns the
+/// number of UIDs that were minted POD.
+pub async fn sync_once(
+    bot: &Bot,
+    connections: &Arc<Connections>,
+    notify: bool,
+) -> anyhow::Result<usize> {
+    // the Telegram user to notify, if the UID is linked
+    let mut uids = BTreeMap::new();
+    for user in connections.registry.indexed_users().await? {
+        uids.insert(user.duolingo_uid, None);
+    }
+    for account in connections.registry.registered_accounts().await? {
+        uids.insert(account.duolingo_uid, account.telegram_user_id);
+    }
+
+    let mut reports = JoinSet::new();
+
+    // Duolingo is queried one UID at a time; only the transactions are left
+    // to run concurrently so they can share a batch
+    for (uid, owner) in uids {
+        let owner = match owner {
+            Some(owner) => Some(owner),
+            None => connections.registry.owner(uid).await?,
+        };
+
+        let total_xp = match connections.duolingo.get_user_total_xp(uid).await {
+            Ok(total_xp) => total_xp,
+            Err(e) => {
+                log::warn!("Failed to fetch XP for {uid}: {e:#}");
+                continue;
+            }
+        };
+
+        let (address, xp_in_contract) = match connections.contract.users(uid.into()).await {
+            Ok(user) => user,
+            Err(e) => {
+                log::warn!("Failed to fetch {uid} from the contract: {e:#}");
+                continue;
+            }
+        };
+        // the index or the registry may be behind an unregistration
+        if address.is_zero() {
+            continue;
+        }
+
+        match XpProgress::new(total_xp, xp_in_contract) {
+            XpProgress::Gained(_) => {}
+            XpProgress::Unchanged => continue,
+            XpProgress::Decreased(xp) => {
+                log::info!("XP for {uid} is {xp} below its high-water mark, holding back");
+                continue;
+            }
+        }
+
+        reports.spawn(report(
+            bot.clone(),
+            connections.clone(),
+            uid,
+            owner,
+            total_xp,
+            notify,
+        ));
+    }
+
+    let mut minted = 0;
+    while let Some(result) = reports.join_next().await {
+        match result? {
+            Ok(true) => minted += 1,
+            Ok(false) => {}
+            Err(e) => log::warn!("{e:#}"),
+        }
+    }
+
+    Ok(minted)
+}
+
+async fn report(
+    bot: Bot,
+    connections: Arc<Connections>,
+    uid: u64,
+    owner: Option<UserId>,
+    total_xp: u64,
+    notify: bool,
+) -> anyhow::Result<bool> {
+    let receipt = match connections.report_xp(uid, total_xp).await? {
+        TxOutcome::Confirmed(receipt) => receipt,
+        TxOutcome::Reverted { reason, tx_hash } => {
+            log::warn!("XP report for {uid} reverted in {tx_hash:?}: {reason:?}");
+            return Ok(false);
+        }
+    };
+
+    let minted = minted_pod(&receipt, uid, total_xp);
+    if let (true, Some(owner), Some(minted)) = (notify, owner, minted) {
+        // private chats share their id with the user
+        let chat_id = ChatId(owner.0 as i64);
+        let text = format!(
+            "You received {minted} POD for your progress on Duolingo! {}",
+            tx_link(&connections.explorer_url, receipt.transaction_hash),
+        );
+
+        if let Err(e) = bot
+            .send_message(chat_id, text)
+            .parse_mode(ParseMode::Html)
+            .await
+        {
+            log::warn!("Failed to notify {chat_id} about minted XP: {e}");
+        }
+    }
+
+    Ok(true)
+}
@@ -0,0 +1,177 @@
+use ethers::{
+    contract::parse_log,
+    providers::{JsonRpcClient, PendingTransaction, Provider},
+    types::{Log, TransactionReceipt, TxHash, U256},
+};
+use reqwest::Url;
+
+use crate::{TransferFilter, XpReportedFilter};
+
+pub const DEFAULT_EXPLORER_URL: &str = "https://hekla.taikoscan.network/";
+
+pub enum TxOutcome {
+    Confirmed(Box<TransactionReceipt>),
+    Reverted {
+        reason: Option<String>,
+        tx_hash: Option<TxHash>,
+    },
+}
+
+pub enum Submission {
+    Sent(TxHash),
+    /// Rejected while estimating gas, so nothing was broadcast.
+    Reverted(String),
+}
+
+/// Waits for `confirmations` blocks on top of the transaction and checks
+/// whether it succeeded.
+pub async fn confirm<P: JsonRpcClient>(
+    provider: &Provider<P>,
+    tx_hash: TxHash,
+    confirmations: usize,
+) -> anyhow::Result<TxOutcome> {
+    let receipt = PendingTransaction::new(tx_hash, provider)
+        .confirmations(confirmations)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("Transaction {tx_hash:?} was dropped from the mempool"))?;
+
+    if receipt.status == Some(1.into()) {
+        Ok(TxOutcome::Confirmed(Box::new(receipt)))
+    } else {
+        Ok(TxOutcome::Reverted {
+            reason: None,
+            tx_hash: Some(tx_hash),
+        })
+    }
+}
+
+/// How much POD the receipt's report of `xp` for `uid` minted, in whole
+/// tokens. A batch can report the same UID more than once, so the report is
+/// matched on the XP too, and the amount comes from the `Transfer` that
+/// `_mintXp` emits right before `XpReported`.
+///
+/// Contracts deployed before `XpReported` was added can't batch either, so
+/// without any reports in the receipt its one mint is taken.
+pub fn minted_pod(receipt: &TransactionReceipt, uid: u64, xp: u64) -> Option<U256> {
+    let has_reports = receipt
+        .logs
+        .iter()
+        .any(|log| parse_log::<XpReportedFilter>(log.clone()).is_ok());
+    if !has_reports {
+        return receipt
+            .logs
+            .iter()
+            .find_map(minted)
+            .map(|amount| amount / U256::exp10(18));
+    }
+
+    receipt.logs.windows(2).find_map(|logs| {
+        let reported = parse_log::<XpReportedFilter>(logs[1].clone()).ok()?;
+        if reported.uid != uid.into() || reported.xp != xp.into() {
+            return None;
+        }
+
+        minted(&logs[0]).map(|amount| amount / U256::exp10(18))
+    })
+}
+
+/// The amount in wei if `log` is a `Transfer` from the zero address.
+pub fn minted(log: &Log) -> Option<U256> {
+    let transfer = parse_log::<TransferFilter>(log.clone()).ok()?;
+    transfer.from.is_zero().then_some(transfer.value)
+}
+
+/// Turns a `require` message from `DuolingoPow.sol` into something a user can
+/// act on.
+pub fn explain_revert(reason: Option<&str>) -> String {
+    match reason {
+        Some("UID is already registered") => {
+            "This Duolingo account is already registered.".to_string()
+        }
+        Some("Address is already registered") => {
+            "That address is already registered to another Duolingo account.".to_string()
+        }
+        Some("UID is not registered") => {
+            "This Duolingo account isn't registered yet. Use /register first.".to_string()
+        }
+        Some("Reported XP must be higher than previous XP") => {
+            "You haven't earned any XP since your last update, so there's nothing to mint."
+                .to_string()
+        }
+        Some("Invalid address") => "That address can't be registered.".to_string(),
+        Some(reason) => format!("The contract rejected the transaction: {reason}"),
+        None => "The transaction was reverted.".to_string(),
+    }
+}
+
+/// An HTML reply for a reverted transaction.
+pub fn describe_revert(
+    explorer_url: &Url,
+    reason: Option<&str>,
+    tx_hash: Option<TxHash>,
+) -> String {
+    let explanation = explain_revert(reason);
+
+    match tx_hash {
+        Some(tx_hash) => format!("{explanation} {}", tx_link(explorer_url, tx_hash)),
+        None => explanation,
+    }
+}
+
+/// An HTML link to the transaction on the block explorer.
+pub fn tx_link(explorer_url: &Url, tx_hash: TxHash) -> String {
+    let hash = format!("{tx_hash:?}");
+    let url = explorer_url
+        .join(&format!("tx/{hash}"))
+        .map(|url| url.to_string())
+        .unwrap_or_default();
+
+    format!(
+        "<a href=\"{url}\">{}…{}</a>",
+        &hash[..10],
+        &hash[hash.len() - 8..]
+    )
+}
+
+#[test]
+fn test_minted_pod_matches_the_report() {
+    use ethers::{
+        abi::{encode, Token},
+        contract::EthEvent,
+        types::{Address, H256},
+    };
+
+    let to = Address::repeat_byte(0xaa);
+    let mint = |pod: u64| Log {
+        topics: vec![
+            TransferFilter::signature(),
+            H256::from(Address::zero()),
+            H256::from(to),
+        ],
+        data: encode(&[Token::Uint(U256::exp10(18) * pod)]).into(),
+        ..Default::default()
+    };
+    let reported = |uid: u64, xp: u64| Log {
+        topics: vec![XpReportedFilter::signature(), H256::from_low_u64_be(uid)],
+        data: encode(&[Token::Uint(xp.into())]).into(),
+        ..Default::default()
+    };
+
+    // two /updates for the same UID batched into one transaction
+    let receipt = TransactionReceipt {
+        logs: vec![mint(30), reported(1001, 130), mint(10), reported(1001, 140)],
+        ..Default::default()
+    };
+
+    assert_eq!(minted_pod(&receipt, 1001, 130), Some(30.into()));
+    assert_eq!(minted_pod(&receipt, 1001, 140), Some(10.into()));
+    assert_eq!(minted_pod(&receipt, 1001, 150), None);
+    assert_eq!(minted_pod(&receipt, 1002, 130), None);
+
+    // from a contract without XpReported
+    let receipt = TransactionReceipt {
+        logs: vec![mint(30)],
+        ..Default::default()
+    };
+    assert_eq!(minted_pod(&receipt, 1001, 130), Some(30.into()));
+}
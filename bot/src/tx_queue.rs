@@ -0,0 +1,447 @@
+use std::time::Duration;
+
+use ethers::{
+    contract::{parse_log, ContractCall, ContractError},
+    providers::{Middleware, MiddlewareError, ProviderError},
+    signers::Signer,
+    types::{transaction::eip2718::TypedTransaction, Address, BlockNumber, TxHash, U256},
+    utils::keccak256,
+};
+use tokio::{
+    sync::{mpsc, oneshot},
+    time::Instant,
+};
+
+use crate::{
+    balance,
+    error::BotError,
+    metrics,
+    tx::{self, Submission, TxOutcome},
+    OwnerContract, OwnerMiddleware, XpReportSkippedFilter, XpReportedFilter,
+};
+
+/// A state change that has to be sent from the owner wallet.
+#[derive(Debug, Clone)]
+pub enum OwnerTx {
+    Register { uid: u64, address: Address, xp: u64 },
+    UpdateAddress { uid: u64, address: Address },
+    ReportXp { uid: u64, xp: u64 },
+    Unregister { uid: u64 },
+}
+
+impl OwnerTx {
+    fn call(&self, contract: &OwnerContract) -> ContractCall<OwnerMiddleware, ()> {
+        match *self {
+            OwnerTx::Register { uid, address, xp } => {
+                contract.user_register(uid.into(), address, xp.into())
+            }
+            OwnerTx::UpdateAddress { uid, address } => {
+                contract.user_update_address(uid.into(), address)
+            }
+            OwnerTx::ReportXp { uid, xp } => contract.report_xp(uid.into(), xp.into()),
+            OwnerTx::Unregister { uid } => contract.user_unregister(uid.into()),
+        }
+    }
+}
+
+type Reply = oneshot::Sender<anyhow::Result<TxOutcome>>;
+
+struct Job {
+    tx: OwnerTx,
+    reply: Reply,
+}
+
+/// Funnels every owner transaction through a single worker, which assigns
+/// the nonces itself. Confirmations are awaited concurrently once a
+/// transaction has been broadcast, and one that never confirms makes the
+/// worker fetch the nonce again, so that it doesn't sign behind a gap.
+///
+/// Nothing is sent while the gas price is above `max_gas_price`, or while the
+/// owner wallet has less than `min_balance`.
+///
+/// With a batch window, XP reports are held back for up to that long and sent
+/// together through `reportXpBatch`.
+#[derive(Clone)]
+pub struct TxQueue {
+    sender: mpsc::Sender<Job>,
+}
+
+impl TxQueue {
+    pub fn spawn(
+        contract: OwnerContract,
+        confirmations: usize,
+        retries: usize,
+        max_gas_price: Option<U256>,
+        min_balance: Option<U256>,
+        batch_window: Option<Duration>,
+    ) -> Self {
+        let (sender, receiver) = mpsc::channel(64);
+        let (unconfirmed, unconfirmed_receiver) = mpsc::unbounded_channel();
+
+        let worker = Worker {
+            contract,
+            confirmations,
+            retries,
+            max_gas_price,
+            min_balance,
+            nonce: None,
+            unconfirmed,
+        };
+        tokio::spawn(worker.run(receiver, unconfirmed_receiver, batch_window));
+
+        Self { sender }
+    }
+
+    /// Queues `tx` and waits for its outcome.
+    pub async fn submit(&self, tx: OwnerTx) -> anyhow::Result<TxOutcome> {
+        let (reply, outcome) = oneshot::channel();
+
+        self.sender
+            .send(Job { tx, reply })
+            .await
+            .map_err(|_| anyhow::anyhow!("The transaction queue has shut down"))?;
+
+        outcome
+            .await
+            .map_err(|_| anyhow::anyhow!("The transaction queue dropped the transaction"))?
+    }
+}
+
+struct XpReport {
+    uid: u64,
+    xp: u64,
+    reply: Reply,
+}
+
+#[derive(Clone)]
+struct Worker {
+    contract: OwnerContract,
+    confirmations: usize,
+    retries: usize,
+    max_gas_price: Option<U256>,
+    min_balance: Option<U256>,
+    /// The nonce for the next transaction, or `None` if it has to be fetched
+    /// from the node first.
+    nonce: Option<U256>,
+    /// Where confirmation tasks report transactions that didn't confirm.
+    unconfirmed: mpsc::UnboundedSender<TxHash>,
+}
+
+impl Worker {
+    async fn run(
+        mut self,
+        mut receiver: mpsc::Receiver<Job>,
+        mut unconfirmed: mpsc::UnboundedReceiver<TxHash>,
+        batch_window: Option<Duration>,
+    ) {
+        let mut batch = Vec::new();
+        let mut deadline = None;
+
+        loop {
+            let job = tokio::select! {
+                job = receiver.recv() => job,
+                // the worker holds a sender itself, so this never ends
+                Some(tx_hash) = unconfirmed.recv() => {
+                    log::warn!("{tx_hash:?} didn't confirm, fetching the nonce again");
+                    self.nonce = None;
+                    continue;
+                }
+                _ = sleep_until(deadline), if deadline.is_some() => {
+                    deadline = None;
+                    self.send_batch(std::mem::take(&mut batch)).await;
+                    continue;
+                }
+            };
+
+            let Some(Job { tx, reply }) = job else {
+                break;
+            };
+
+            match (tx, batch_window) {
+                (OwnerTx::ReportXp { uid, xp }, Some(window)) => {
+                    deadline.get_or_insert_with(|| Instant::now() + window);
+                    batch.push(XpReport { uid, xp, reply });
+                }
+                (tx, _) => self.send_one(tx, reply).await,
+            }
+        }
+
+        self.send_batch(batch).await;
+    }
+
+    async fn send_one(&mut self, tx: OwnerTx, reply: Reply) {
+        let call = tx.call(&self.contract).tx;
+        match self.send_with_retries(&format!("{tx:?}"), call).await {
+            Ok(Submission::Sent(tx_hash)) => {
+                log::info!("Sent {tx:?} in {tx_hash:?}");
+                metrics::tx_sent();
+                let worker = self.clone();
+                tokio::spawn(async move {
+                    // the submitter may have given up waiting
+                    let _ = reply.send(worker.confirm(tx_hash).await);
+                });
+            }
+            Ok(Submission::Reverted(reason)) => {
+                let outcome = TxOutcome::Reverted {
+                    reason: Some(reason),
+                    tx_hash: None,
+                };
+                metrics::tx_settled(&outcome);
+                let _ = reply.send(Ok(outcome));
+            }
+            Err(e) => {
+                let _ = reply.send(Err(e));
+            }
+        }
+    }
+
+    async fn send_batch(&mut self, mut batch: Vec<XpReport>) {
+        if batch.len() <= 1 {
+            if let Some(XpReport { uid, xp, reply }) = batch.pop() {
+                self.send_one(OwnerTx::ReportXp { uid, xp }, reply).await;
+            }
+            return;
+        }
+
+        let uids: Vec<U256> = batch.iter().map(|r| r.uid.into()).collect();
+        let xps: Vec<U256> = batch.iter().map(|r| r.xp.into()).collect();
+        let label = format!("XP batch of {}", batch.len());
+
+        let call = self.contract.report_xp_batch(uids, xps).tx;
+        let submission = self.send_with_retries(&label, call).await;
+
+        let tx_hash = match submission {
+            Ok(Submission::Sent(tx_hash)) => tx_hash,
+            Ok(Submission::Reverted(reason)) => {
+                metrics::tx_settled(&TxOutcome::Reverted {
+                    reason: Some(reason.clone()),
+                    tx_hash: None,
+                });
+                for report in batch {
+                    let _ = report.reply.send(Ok(TxOutcome::Reverted {
+                        reason: Some(reason.clone()),
+                        tx_hash: None,
+                    }));
+                }
+                return;
+            }
+            Err(e) => {
+                let message = format!("{e:#}");
+                for report in batch {
+                    let _ = report.reply.send(Err(anyhow::anyhow!(message.clone())));
+                }
+                return;
+            }
+        };
+
+        log::info!("Sent {label} in {tx_hash:?}");
+        metrics::tx_sent();
+        let worker = self.clone();
+        tokio::spawn(async move { worker.confirm_batch(tx_hash, batch).await });
+    }
+
+    async fn send_with_retries(
+        &mut self,
+        label: &str,
+        tx: TypedTransaction,
+    ) -> anyhow::Result<Submission> {
+        if let Some(max) = self.max_gas_price {
+            let price = self.contract.client().get_gas_price().await?;
+            if price > max {
+                log::warn!("Not sending {label}: the gas price is {price} wei");
+                return Err(BotError::GasPriceTooHigh { price, max }.into());
+            }
+        }
+
+        if let Some(min) = self.min_balance {
+            let balance = balance::owner_balance(&self.contract).await?;
+            if balance < min {
+                log::warn!("Not sending {label}: the owner balance is {balance} wei");
+                return Err(BotError::LowBalance { balance, min }.into());
+            }
+        }
+
+        let mut attempt = 0;
+
+        loop {
+            match self.send(tx.clone()).await {
+                Err(e) if attempt < self.retries && is_transient(&e) => {
+                    attempt += 1;
+                    log::warn!(
+                        "Failed to send {label} (attempt {attempt}/{}): {e:#}",
+                        self.retries
+                    );
+                    tokio::time::sleep(Duration::from_secs(1 << attempt.min(5))).await;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Signs `tx` with the next nonce and broadcasts it. The nonce only moves
+    /// on once the transaction is known to be in the mempool. Since the hash
+    /// is known before broadcasting, a send that failed on our side but
+    /// reached the node anyway is found rather than sent a second time.
+    async fn send(&mut self, mut tx: TypedTransaction) -> anyhow::Result<Submission> {
+        let client = self.contract.client();
+        let owner = client.address();
+
+        let nonce = match self.nonce.take() {
+            Some(nonce) => nonce,
+            None => pending_nonce(&client, owner).await?,
+        };
+        tx.set_nonce(nonce);
+
+        if let Err(e) = client.fill_transaction(&mut tx, None).await {
+            // nothing was broadcast, so the nonce is still free
+            self.nonce = Some(nonce);
+
+            let e = ContractError::<OwnerMiddleware>::from_middleware_error(e);
+            return match e.decode_revert::<String>() {
+                Some(reason) => Ok(Submission::Reverted(reason)),
+                None => Err(e.into()),
+            };
+        }
+
+        let signature = client.signer().sign_transaction(&tx).await?;
+        let raw = tx.rlp_signed(&signature);
+        let tx_hash = TxHash::from(keccak256(&raw));
+
+        let error = match client.send_raw_transaction(raw).await {
+            Ok(_) => {
+                self.nonce = Some(nonce + 1);
+                return Ok(Submission::Sent(tx_hash));
+            }
+            Err(e) => e,
+        };
+
+        // compare against the pending count rather than the latest, which
+        // lags behind our own transactions that are still being confirmed
+        let pending = pending_nonce(&client, owner).await?;
+        if pending > nonce && client.get_transaction(tx_hash).await?.is_some() {
+            log::warn!("Sending {tx_hash:?} failed, but the node has it: {error}");
+            self.nonce = Some(nonce + 1);
+            return Ok(Submission::Sent(tx_hash));
+        }
+
+        self.nonce = Some(pending);
+        Err(error.into())
+    }
+
+    async fn confirm(&self, tx_hash: TxHash) -> anyhow::Result<TxOutcome> {
+        let outcome = tx::confirm(
+            self.contract.client().provider(),
+            tx_hash,
+            self.confirmations,
+        )
+        .await
+        .inspect_err(|_| {
+            // the nonce it used may be free again
+            let _ = self.unconfirmed.send(tx_hash);
+        })?;
+        metrics::tx_settled(&outcome);
+
+        Ok(outcome)
+    }
+
+    /// Splits the batch receipt back into one outcome per report.
+    async fn confirm_batch(&self, tx_hash: TxHash, batch: Vec<XpReport>) {
+        let receipt = match self.confirm(tx_hash).await {
+            Ok(TxOutcome::Confirmed(receipt)) => receipt,
+            Ok(TxOutcome::Reverted { reason, tx_hash }) => {
+                for report in batch {
+                    let _ = report.reply.send(Ok(TxOutcome::Reverted {
+                        reason: reason.clone(),
+                        tx_hash,
+                    }));
+                }
+                return;
+            }
+            Err(e) => {
+                let message = format!("{e:#}");
+                for report in batch {
+                    let _ = report.reply.send(Err(anyhow::anyhow!(message.clone())));
+                }
+                return;
+            }
+        };
+
+        let mut reported = Vec::new();
+        let mut skipped = Vec::new();
+        for log in receipt.logs.iter().cloned() {
+            if let Ok(event) = parse_log::<XpReportedFilter>(log.clone()) {
+                reported.push(event.uid.as_u64());
+            } else if let Ok(event) = parse_log::<XpReportSkippedFilter>(log) {
+                skipped.push((event.uid.as_u64(), event.reason));
+            }
+        }
+
+        for XpReport { uid, reply, .. } in batch {
+            // a uid can appear twice if a user updated twice within the window
+            let outcome = if let Some(i) = reported.iter().position(|&r| r == uid) {
+                reported.swap_remove(i);
+                TxOutcome::Confirmed(receipt.clone())
+            } else {
+                let reason = skipped
+                    .iter()
+                    .position(|(s, _)| *s == uid)
+                    .map(|i| skipped.swap_remove(i).1);
+                TxOutcome::Reverted {
+                    reason,
+                    tx_hash: Some(tx_hash),
+                }
+            };
+            let _ = reply.send(Ok(outcome));
+        }
+    }
+}
+
+/// Whether the node never answered, as opposed to turning the transaction
+/// down, which sending it again won't change.
+fn is_transient(e: &anyhow::Error) -> bool {
+    let provider_error = if let Some(e) = e.downcast_ref::<ContractError<OwnerMiddleware>>() {
+        e.as_middleware_error().and_then(MiddlewareError::as_inner)
+    } else if let Some(e) = e.downcast_ref::<<OwnerMiddleware as Middleware>::Error>() {
+        e.as_inner()
+    } else {
+        e.downcast_ref::<ProviderError>()
+    };
+
+    match provider_error {
+        Some(ProviderError::HTTPError(_)) => true,
+        Some(ProviderError::JsonRpcClientError(e)) => e.as_error_response().is_none(),
+        _ => false,
+    }
+}
+
+async fn pending_nonce(client: &OwnerMiddleware, owner: Address) -> anyhow::Result<U256> {
+    Ok(client
+        .get_transaction_count(owner, Some(BlockNumber::Pending.into()))
+        .await?)
+}
+
+async fn sleep_until(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+#[tokio::test]
+async fn test_only_unanswered_requests_are_retried() {
+    use ethers::providers::{HttpClientError, JsonRpcError};
+
+    let e = reqwest::get("http://127.0.0.1:1/").await.unwrap_err();
+    let unreachable = ProviderError::JsonRpcClientError(Box::new(HttpClientError::from(e)));
+    assert!(is_transient(&unreachable.into()));
+
+    let rejected =
+        ProviderError::JsonRpcClientError(Box::new(HttpClientError::JsonRpcError(JsonRpcError {
+            code: -32000,
+            message: "nonce too low".to_string(),
+            data: None,
+        })));
+    assert!(!is_transient(&rejected.into()));
+
+    assert!(!is_transient(&anyhow::anyhow!("signing failed")));
+}
@@ -0,0 +1,63 @@
+use std::{convert::Infallible, net::TcpListener};
+
+use reqwest::Url;
+use teloxide::{
+    prelude::*,
+    update_listeners::{webhooks, UpdateListener},
+};
+
+/// Checks a secret token against what Telegram accepts: 1-256 characters of
+/// `A-Z`, `a-z`, `0-9`, `_` and `-`.
+pub fn parse_secret(secret: &str) -> Result<String, String> {
+    if secret.is_empty() || secret.len() > 256 {
+        return Err("must be between 1 and 256 characters long".to_string());
+    }
+
+    if let Some(c) = secret
+        .chars()
+        .find(|c| !(c.is_ascii_alphanumeric() || *c == '_' || *c == '-'))
+    {
+        return Err(format!(
+            "contains {c:?}, but only A-Z, a-z, 0-9, _ and - are allowed"
+        ));
+    }
+
+    Ok(secret.to_owned())
+}
+
+/// Registers `url` as the bot's webhook and serves it on `listener`. Updates
+/// without the secret token are rejected; a random one is used if `secret` is
+/// `None`. The webhook is removed again when the returned listener stops.
+///
+/// `url` is where Telegram sends updates, e.g. a reverse proxy in front of
+/// `listener`, but its path has to match the path the proxy forwards to.
+pub async fn listen(
+    bot: Bot,
+    url: Url,
+    listener: TcpListener,
+    secret: Option<String>,
+) -> anyhow::Result<impl UpdateListener<Err = Infallible>> {
+    listener.set_nonblocking(true)?;
+    let mut options = webhooks::Options::new(listener.local_addr()?, url);
+    if let Some(secret) = secret {
+        options = options.secret_token(secret);
+    }
+
+    // bound before the webhook is set so Telegram never points at nothing
+    let server = axum::Server::from_tcp(listener)?;
+    let (mut updates, stopped, router) = webhooks::axum_to_router(bot, options).await?;
+    let stop_token = updates.stop_token();
+
+    tokio::spawn(async move {
+        if let Err(e) = server
+            .serve(router.into_make_service())
+            .with_graceful_shutdown(stopped)
+            .await
+        {
+            log::error!("Webhook server stopped: {e}");
+            stop_token.stop();
+        }
+    });
+
+    Ok(updates)
+}
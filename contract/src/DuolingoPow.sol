@@ -18,6 +18,10 @@ contract DuolingoPow is ERC20Base {
         address indexed _address
     );
 
+    event XpReported(uint256 indexed _uid, uint256 _xp);
+
+    event XpReportSkipped(uint256 indexed _uid, string _reason);
+
     function userRegister(
         uint256 _uid,
         address _address,
@@ -69,12 +73,41 @@ contract DuolingoPow is ERC20Base {
         uint256 _uid,
         uint256 _xp
     ) external onlyOwner requireRegisteredUid(_uid) {
-        // TODO: this assumes that XP can only increase, which is probably not true (deleting courses?)
+        // XP can go down (deleting courses?), in which case the bot holds back
+        // reports until it's higher than this high-water mark again
         require(
             _xp > users[_uid].xp,
             "Reported XP must be higher than previous XP"
         );
 
+        _mintXp(_uid, _xp);
+    }
+
+    // entries that `reportXp` would reject are skipped rather than reverting,
+    // so one stale report doesn't sink everyone else's
+    function reportXpBatch(
+        uint256[] calldata _uids,
+        uint256[] calldata _xps
+    ) external onlyOwner {
+        require(_uids.length == _xps.length, "Array lengths differ");
+
+        for (uint256 i = 0; i < _uids.length; i++) {
+            uint256 _uid = _uids[i];
+
+            if (users[_uid].addr == address(0)) {
+                emit XpReportSkipped(_uid, "UID is not registered");
+            } else if (_xps[i] <= users[_uid].xp) {
+                emit XpReportSkipped(
+                    _uid,
+                    "Reported XP must be higher than previous XP"
+                );
+            } else {
+                _mintXp(_uid, _xps[i]);
+            }
+        }
+    }
+
+    function _mintXp(uint256 _uid, uint256 _xp) internal {
         uint256 delta;
         unchecked {
             delta = _xp - users[_uid].xp;
@@ -82,6 +115,8 @@ contract DuolingoPow is ERC20Base {
         mintTo(users[_uid].addr, delta * 1 ether);
 
         users[_uid].xp = _xp;
+
+        emit XpReported(_uid, _xp);
     }
 
     constructor(
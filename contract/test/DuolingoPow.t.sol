@@ -23,4 +23,30 @@ contract DuolingoPowTest is Test {
 
         assertEq(duo.balanceOf(address(1)), 20 ether);
     }
+
+    function test_reportXpBatch() public {
+        duo.userRegister(1, address(1), 100);
+        duo.userRegister(2, address(2), 50);
+
+        uint256[] memory uids = new uint256[](3);
+        uint256[] memory xps = new uint256[](3);
+        (uids[0], xps[0]) = (1, 130);
+        (uids[1], xps[1]) = (2, 50); // no new xp
+        (uids[2], xps[2]) = (3, 10); // not registered
+
+        duo.reportXpBatch(uids, xps);
+
+        assertEq(duo.balanceOf(address(1)), 30 ether);
+        assertEq(duo.balanceOf(address(2)), 0);
+        (, uint256 xp) = duo.users(1);
+        assertEq(xp, 130);
+    }
+
+    function test_reportXpBatchOnlyOwner() public {
+        uint256[] memory uids = new uint256[](0);
+
+        vm.prank(address(1));
+        vm.expectRevert();
+        duo.reportXpBatch(uids, uids);
+    }
 }